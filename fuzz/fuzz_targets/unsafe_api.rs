@@ -0,0 +1,194 @@
+//! Drives random sequences of the unsafe lend, borrow and reclaim api that
+//! respect its contracts, and checks every result against a model of the
+//! cell's state.
+
+#![no_main]
+
+use std::{
+  ffi::c_void,
+  sync::{Arc, Mutex},
+};
+
+use ffi_cell::{
+  BorrowError, DetachedBorrow, FfiCell, FfiGuard, LendError, ReclaimError,
+  SharedGuard,
+};
+use libfuzzer_sys::{
+  arbitrary::{self, Arbitrary},
+  fuzz_target,
+};
+
+const OBJECTS: usize = 4;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+  Lend(u8),
+  LendWithFinalizer(u8),
+  Borrow,
+  Write(i64),
+  Release,
+  Detach,
+  Reattach,
+  IntoRaw,
+  FromRaw,
+  BorrowShared,
+  ReleaseShared,
+  Reclaim,
+  Reset,
+  ForceReclaim,
+}
+
+fuzz_target!(|ops: Vec<Op>| run(ops));
+
+fn run(ops: Vec<Op>) {
+  // The objects are boxed so their addresses stay put, and they are only
+  // touched directly once the cell no longer points at any of them.
+  let mut objects: Vec<Box<i64>> = (0..OBJECTS as i64).map(Box::new).collect();
+  let mut expected: Vec<i64> = (0..OBJECTS as i64).collect();
+  let finalized = Arc::new(Mutex::new(Vec::new()));
+  let mut expected_finalized = Vec::new();
+
+  let cell = FfiCell::<i64>::new();
+  // The lent object's index, and whether it was lent with a finalizer.
+  let mut lent: Option<(usize, bool)> = None;
+  let mut guard: Option<FfiGuard<'_, i64>> = None;
+  let mut detached: Option<DetachedBorrow<'_, i64>> = None;
+  let mut raw: Option<*mut c_void> = None;
+  let mut shared: Vec<SharedGuard<'_, i64>> = Vec::new();
+
+  for op in ops {
+    let exclusive = guard.is_some() || detached.is_some() || raw.is_some();
+    let borrowed = exclusive || !shared.is_empty();
+    // Never create a reference to the object that is currently lent.
+    let pick = |index: u8| match lent {
+      Some((lent, _)) => (lent + 1 + index as usize % (OBJECTS - 1)) % OBJECTS,
+      None => index as usize % OBJECTS,
+    };
+    match op {
+      Op::Lend(index) => {
+        let index = pick(index);
+        let result = unsafe { cell.try_lend(objects[index].as_mut()) };
+        match (result, lent) {
+          (Err(LendError::AlreadyLent), Some(_)) if exclusive => {},
+          (Err(LendError::AlreadyHasLoan), Some(_)) if !exclusive => {},
+          (Ok(()), None) => lent = Some((index, false)),
+          (result, _) => panic!("unexpected lend result {result:?}"),
+        }
+      },
+      Op::LendWithFinalizer(index) => {
+        let index = pick(index);
+        let finalized = Arc::clone(&finalized);
+        let result = unsafe {
+          cell.try_lend_with_finalizer(objects[index].as_mut(), move |value| {
+            finalized.lock().unwrap().push(*value)
+          })
+        };
+        match (result, lent) {
+          (Err(LendError::AlreadyLent), Some(_)) if exclusive => {},
+          (Err(LendError::AlreadyHasLoan), Some(_)) if !exclusive => {},
+          (Ok(()), None) => lent = Some((index, true)),
+          (result, _) => {
+            panic!("unexpected lend with finalizer result {result:?}")
+          },
+        }
+      },
+      Op::Borrow => match (cell.try_borrow(), lent) {
+        (Err(BorrowError::AlreadyBorrowed), Some(_)) if borrowed => {},
+        (Err(BorrowError::Unavailable), None) => {},
+        (Ok(borrow), Some((index, _))) if !borrowed => {
+          assert_eq!(*borrow, expected[index], "borrow saw a stale value");
+          guard = Some(borrow);
+        },
+        (result, _) => panic!("unexpected borrow result {:?}", result.err()),
+      },
+      Op::Write(value) => {
+        if let (Some(guard), Some((index, _))) = (&mut guard, lent) {
+          **guard = value;
+          expected[index] = value;
+        }
+      },
+      Op::Release => drop(guard.take()),
+      Op::Detach => {
+        if let Some(borrow) = guard.take() {
+          detached = Some(borrow.detach());
+        }
+      },
+      Op::Reattach => {
+        if let Some(handle) = detached.take() {
+          guard = Some(handle.reattach());
+        }
+      },
+      Op::IntoRaw => {
+        if let Some(handle) = detached.take() {
+          raw = Some(handle.into_raw());
+        }
+      },
+      Op::FromRaw => {
+        if let Some(ptr) = raw.take() {
+          detached = Some(unsafe { DetachedBorrow::from_raw(ptr) });
+        }
+      },
+      Op::BorrowShared => match (cell.try_borrow_shared(), lent) {
+        (Err(BorrowError::AlreadyBorrowed), Some(_)) if exclusive => {},
+        (Err(BorrowError::Unavailable), None) => {},
+        (Ok(borrow), Some((index, _))) if !exclusive => {
+          assert_eq!(*borrow, expected[index], "borrow saw a stale value");
+          shared.push(borrow);
+        },
+        (result, _) => {
+          panic!("unexpected shared borrow result {:?}", result.err())
+        },
+      },
+      Op::ReleaseShared => drop(shared.pop()),
+      Op::Reclaim => match (cell.try_reclaim(), lent) {
+        (Err(ReclaimError::InUse), Some(_)) if borrowed => {},
+        (Err(ReclaimError::Unavailable), None) => {},
+        (Ok(()), Some((index, finalizer))) if !borrowed => {
+          if finalizer {
+            expected_finalized.push(expected[index]);
+          }
+          lent = None;
+        },
+        (result, _) => panic!("unexpected reclaim result {result:?}"),
+      },
+      Op::Reset => match cell.reset() {
+        Err(ReclaimError::InUse) if borrowed => {},
+        Ok(()) if !borrowed => {
+          if let Some((index, true)) = lent {
+            expected_finalized.push(expected[index]);
+          }
+          lent = None;
+        },
+        result => panic!("unexpected reset result {result:?}"),
+      },
+      Op::ForceReclaim => {
+        // Forcing a reclaim is only allowed with no borrow alive, and it
+        // drops the finalizer without running it.
+        if !borrowed {
+          unsafe { cell.force_reclaim() };
+          lent = None;
+        }
+      },
+    }
+  }
+
+  drop(guard);
+  drop(detached);
+  if let Some(ptr) = raw {
+    drop(unsafe { DetachedBorrow::<i64>::from_raw(ptr) });
+  }
+  drop(shared);
+  if let Some((index, true)) = lent {
+    expected_finalized.push(expected[index]);
+  }
+  cell
+    .reset()
+    .expect("cell should reset once all borrows are released");
+  let actual: Vec<i64> = objects.iter().map(|object| **object).collect();
+  assert_eq!(actual, expected, "writes through borrows were lost");
+  assert_eq!(
+    *finalized.lock().unwrap(),
+    expected_finalized,
+    "finalizers ran on the wrong values"
+  );
+}
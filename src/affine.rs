@@ -0,0 +1,166 @@
+use std::{
+  marker::PhantomData,
+  ops::{Deref, DerefMut},
+  sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+  BorrowError, Error, FfiCell, FfiGuard, LendError, ReclaimError, ResultExt,
+  ScopeGuard,
+};
+
+/// A cell for values that are [`Send`] but not [`Sync`].
+///
+/// The thread that lends a value is the only one that may borrow or reclaim
+/// it until it has been reclaimed. Operations from any other thread fail
+/// with a `WrongThread` error.
+pub struct AffineFfiCell<T: Send> {
+  inner: FfiCell<AssertSync<T>>,
+  owner: AtomicU64,
+}
+
+impl<T: Send> AffineFfiCell<T> {
+  pub const fn new() -> Self {
+    Self {
+      inner: FfiCell::new(),
+      owner: AtomicU64::new(NO_OWNER),
+    }
+  }
+
+  #[track_caller]
+  pub fn run<R>(&self, object: &mut T, f: impl FnOnce() -> R) -> R {
+    self.try_run(object, f).unwrap_or_display_err()
+  }
+
+  pub fn try_run<R>(
+    &self,
+    object: &mut T,
+    f: impl FnOnce() -> R,
+  ) -> Result<R, Error> {
+    unsafe {
+      self.try_lend(object)?;
+    }
+    let _reclaim = ScopeGuard::new(|| self.reclaim());
+    Ok(f())
+  }
+
+  /// # Safety
+  /// The object pointed to in the params cannot be referenced until
+  /// `reclaim` is called without panicking or `try_reclaim` is called and
+  /// returns `Ok`.
+  #[track_caller]
+  pub unsafe fn lend(&self, ptr: &mut T) {
+    unsafe { self.try_lend(ptr).unwrap_or_display_err() }
+  }
+
+  /// # Safety
+  /// The object pointed to in the params cannot be referenced until
+  /// `reclaim` is called without panicking or `try_reclaim` is called and
+  /// returns `Ok`.
+  pub unsafe fn try_lend(&self, ptr: &mut T) -> Result<(), LendError> {
+    let thread = current_thread();
+    match self.owner.compare_exchange(
+      NO_OWNER,
+      thread,
+      Ordering::SeqCst,
+      Ordering::SeqCst,
+    ) {
+      Ok(_) => {
+        let result = unsafe { self.inner.try_lend(AssertSync::from_mut(ptr)) };
+        if result.is_err() {
+          self.owner.store(NO_OWNER, Ordering::SeqCst);
+        }
+        result
+      },
+      Err(owner) if owner == thread => unsafe {
+        self.inner.try_lend(AssertSync::from_mut(ptr))
+      },
+      Err(_) => Err(LendError::WrongThread),
+    }
+  }
+
+  #[track_caller]
+  pub fn borrow(&self) -> AffineGuard<'_, T> {
+    self.try_borrow().unwrap_or_display_err()
+  }
+
+  pub fn try_borrow(&self) -> Result<AffineGuard<'_, T>, BorrowError> {
+    match self.owner.load(Ordering::SeqCst) {
+      NO_OWNER => Err(BorrowError::Unavailable),
+      owner if owner == current_thread() => self
+        .inner
+        .try_borrow()
+        .map(|inner| AffineGuard { inner, _marker: PhantomData }),
+      _ => Err(BorrowError::WrongThread),
+    }
+  }
+
+  #[track_caller]
+  pub fn reclaim(&self) {
+    self.try_reclaim().unwrap_or_display_err()
+  }
+
+  pub fn try_reclaim(&self) -> Result<(), ReclaimError> {
+    match self.owner.load(Ordering::SeqCst) {
+      NO_OWNER => return Err(ReclaimError::Unavailable),
+      owner if owner != current_thread() => {
+        return Err(ReclaimError::WrongThread);
+      },
+      _ => {},
+    }
+    self.inner.try_reclaim()?;
+    self.owner.store(NO_OWNER, Ordering::SeqCst);
+    Ok(())
+  }
+}
+
+impl<T: Send> Default for AffineFfiCell<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+pub struct AffineGuard<'g, T: Send> {
+  inner: FfiGuard<'g, AssertSync<T>>,
+  // Keeps the guard from being `Sync` when `T` is not, which the wrapped
+  // guard would otherwise be.
+  _marker: PhantomData<&'g mut T>,
+}
+
+impl<'g, T: Send> Deref for AffineGuard<'g, T> {
+  type Target = T;
+
+  fn deref(&self) -> &Self::Target {
+    &self.inner.0
+  }
+}
+
+impl<'g, T: Send> DerefMut for AffineGuard<'g, T> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.inner.0
+  }
+}
+
+/// Lets a `!Sync` value through [`FfiCell`]'s bounds. This is sound because
+/// the value is only ever reached through an exclusive borrow, which needs
+/// no more than `T: Send`, and [`AffineGuard`] restores the `Sync` bound.
+#[repr(transparent)]
+struct AssertSync<T>(T);
+
+unsafe impl<T> Sync for AssertSync<T> {}
+
+impl<T> AssertSync<T> {
+  fn from_mut(value: &mut T) -> &mut Self {
+    unsafe { &mut *(value as *mut T).cast::<Self>() }
+  }
+}
+
+const NO_OWNER: u64 = 0;
+
+fn current_thread() -> u64 {
+  static NEXT: AtomicU64 = AtomicU64::new(NO_OWNER + 1);
+  thread_local! {
+    static ID: u64 = NEXT.fetch_add(1, Ordering::Relaxed);
+  }
+  ID.with(|id| *id)
+}
@@ -0,0 +1,121 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+  BorrowError, FfiCell, FfiGuard, LendError, ReclaimError, ResultExt,
+};
+
+/// A fixed number of [`FfiCell`]s that are handed out by index.
+///
+/// Lending picks the lowest free slot and returns its index, and reclaiming
+/// a slot frees its index for the next lend. Each slot has its own loan and
+/// borrow state.
+pub struct FfiArena<T: Sync> {
+  slots: Box<[Slot<T>]>,
+}
+
+struct Slot<T: Sync> {
+  occupied: AtomicBool,
+  cell: FfiCell<T>,
+}
+
+impl<T: Sync> FfiArena<T> {
+  pub fn with_capacity(capacity: usize) -> Self {
+    Self {
+      slots: (0..capacity)
+        .map(|_| Slot {
+          occupied: AtomicBool::new(false),
+          cell: FfiCell::new(),
+        })
+        .collect(),
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.slots.len()
+  }
+
+  /// # Safety
+  /// The object pointed to in the params cannot be referenced until its
+  /// index is reclaimed with `reclaim` without panicking or `try_reclaim`
+  /// returning `Ok`.
+  #[track_caller]
+  pub unsafe fn lend(&self, ptr: &mut T) -> usize {
+    unsafe { self.try_lend(ptr).unwrap_or_display_err() }
+  }
+
+  /// # Safety
+  /// The object pointed to in the params cannot be referenced until its
+  /// index is reclaimed with `reclaim` without panicking or `try_reclaim`
+  /// returning `Ok`.
+  pub unsafe fn try_lend(&self, ptr: &mut T) -> Result<usize, LendError> {
+    let (index, slot) = self
+      .slots
+      .iter()
+      .enumerate()
+      .find(|(_, slot)| {
+        slot
+          .occupied
+          .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+          .is_ok()
+      })
+      .ok_or(LendError::Full)?;
+    unsafe { slot.cell.try_lend(ptr) }.inspect_err(|_| {
+      slot.occupied.store(false, Ordering::SeqCst);
+    })?;
+    Ok(index)
+  }
+
+  #[track_caller]
+  pub fn borrow(&self, index: usize) -> FfiGuard<'_, T> {
+    self.try_borrow(index).unwrap_or_display_err()
+  }
+
+  pub fn try_borrow(
+    &self,
+    index: usize,
+  ) -> Result<FfiGuard<'_, T>, BorrowError> {
+    self
+      .slots
+      .get(index)
+      .ok_or(BorrowError::Unavailable)?
+      .cell
+      .try_borrow()
+  }
+
+  #[track_caller]
+  pub fn borrow_many(&self, indices: &[usize]) -> Vec<FfiGuard<'_, T>> {
+    self.try_borrow_many(indices).unwrap_or_display_err()
+  }
+
+  /// Borrows every slot in `indices`, or none of them if any borrow fails.
+  ///
+  /// Slots are acquired in index order regardless of the order requested,
+  /// so concurrent multi-borrows contend for them in a consistent order.
+  /// The guards are returned in the order of `indices`.
+  pub fn try_borrow_many(
+    &self,
+    indices: &[usize],
+  ) -> Result<Vec<FfiGuard<'_, T>>, BorrowError> {
+    let mut order: Vec<usize> = (0..indices.len()).collect();
+    order.sort_by_key(|&position| indices[position]);
+
+    let mut guards: Vec<Option<FfiGuard<'_, T>>> =
+      indices.iter().map(|_| None).collect();
+    for position in order {
+      guards[position] = Some(self.try_borrow(indices[position])?);
+    }
+    Ok(guards.into_iter().flatten().collect())
+  }
+
+  #[track_caller]
+  pub fn reclaim(&self, index: usize) {
+    self.try_reclaim(index).unwrap_or_display_err()
+  }
+
+  pub fn try_reclaim(&self, index: usize) -> Result<(), ReclaimError> {
+    let slot = self.slots.get(index).ok_or(ReclaimError::Unavailable)?;
+    slot.cell.try_reclaim()?;
+    slot.occupied.store(false, Ordering::SeqCst);
+    Ok(())
+  }
+}
@@ -0,0 +1,88 @@
+use std::{
+  cell::UnsafeCell,
+  marker::PhantomData,
+  ops::{Deref, DerefMut},
+  sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{BorrowError, ResultExt};
+
+/// A cell that stores a small `Copy` value inline instead of pointing at a
+/// lent one, so there is nothing to lend or reclaim.
+pub struct InlineFfiCell<T: Copy> {
+  value: UnsafeCell<T>,
+  in_use: AtomicBool,
+}
+
+unsafe impl<T: Copy + Send> Sync for InlineFfiCell<T> {}
+
+impl<T: Copy> InlineFfiCell<T> {
+  pub const fn new(value: T) -> Self {
+    Self {
+      value: UnsafeCell::new(value),
+      in_use: AtomicBool::new(false),
+    }
+  }
+
+  #[track_caller]
+  pub fn borrow(&self) -> InlineGuard<'_, T> {
+    self.try_borrow().unwrap_or_display_err()
+  }
+
+  pub fn try_borrow(&self) -> Result<InlineGuard<'_, T>, BorrowError> {
+    if self.in_use.swap(true, Ordering::SeqCst) {
+      Err(BorrowError::AlreadyBorrowed)
+    } else {
+      Ok(InlineGuard { cell: self, _marker: PhantomData })
+    }
+  }
+
+  pub fn into_inner(self) -> T {
+    self.value.into_inner()
+  }
+}
+
+impl<T: Copy + Default> Default for InlineFfiCell<T> {
+  fn default() -> Self {
+    Self::new(T::default())
+  }
+}
+
+/// Like `&mut T`, the guard is [`Sync`] only if `T` is:
+///
+/// ```compile_fail
+/// # use ffi_cell::InlineGuard;
+/// # use std::{cell::Cell, marker::PhantomData};
+/// #[derive(Clone, Copy)]
+/// struct NotSync(PhantomData<Cell<u8>>);
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<InlineGuard<'static, NotSync>>();
+/// ```
+pub struct InlineGuard<'g, T: Copy> {
+  cell: &'g InlineFfiCell<T>,
+  _marker: PhantomData<&'g mut T>,
+}
+
+// A shared guard only hands out `&T`, so it needs `T: Sync` rather than
+// the `T: Send` that sharing the cell itself needs.
+unsafe impl<'g, T: Copy + Sync> Sync for InlineGuard<'g, T> {}
+
+impl<'g, T: Copy> Deref for InlineGuard<'g, T> {
+  type Target = T;
+
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*self.cell.value.get() }
+  }
+}
+
+impl<'g, T: Copy> DerefMut for InlineGuard<'g, T> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    unsafe { &mut *self.cell.value.get() }
+  }
+}
+
+impl<'g, T: Copy> Drop for InlineGuard<'g, T> {
+  fn drop(&mut self) {
+    self.cell.in_use.store(false, Ordering::SeqCst);
+  }
+}
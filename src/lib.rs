@@ -1,19 +1,77 @@
 use std::{
+  any::type_name,
+  cell::UnsafeCell,
+  ffi::{c_int, c_void},
   fmt::Display,
+  io::{self, Read, Write},
   marker::PhantomData,
+  mem::ManuallyDrop,
   ops::{Deref, DerefMut},
+  panic::{AssertUnwindSafe, catch_unwind, resume_unwind},
   ptr::{NonNull, null_mut},
-  sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+  sync::atomic::{
+    AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering, fence,
+  },
+  thread,
 };
 
 use derive_more::{Display, Error, From};
+#[cfg(feature = "contention-hook")]
+use std::sync::OnceLock;
 
+pub use affine::{AffineFfiCell, AffineGuard};
+pub use arena::FfiArena;
+pub use inline::{InlineFfiCell, InlineGuard};
+pub use receipt::{LoanReceipt, ReceiptPolicy};
+pub use trampoline::Trampoline;
+pub use typestate::{Borrowed, Lent};
+
+mod affine;
+mod arena;
+mod inline;
+mod receipt;
 #[cfg(test)]
 mod test;
+mod trampoline;
+mod typestate;
+
+/// Returned by [`FfiCell::borrow_to_c_status`] when the callback ran to
+/// completion.
+pub const STATUS_OK: c_int = 0;
+/// Returned by [`FfiCell::borrow_to_c_status`] when the value could not be
+/// borrowed, so the callback did not run.
+pub const STATUS_BORROW_FAILED: c_int = -1;
+/// Returned by [`FfiCell::borrow_to_c_status`] when the callback panicked.
+/// The panic was caught and the borrow released.
+pub const STATUS_PANICKED: c_int = -2;
 
+/// Lends a value to foreign code, which borrows it back through a shared
+/// reference to the cell.
+///
+/// # Pinning
+/// Every operation takes `&self` and none of them move the cell or rely on
+/// its address staying the same between calls, so a cell that lives inside
+/// a pinned structure can be used through `Pin<&FfiCell<T>>` (or a pinned
+/// parent) by deref. Pinning the cell does not pin the lent value.
+// `repr(C)` keeps the layout independent of `T`, which `as_inner_cell`
+// relies on.
+#[repr(C)]
 pub struct FfiCell<T: Sync> {
   ptr: AtomicPtr<T>,
   in_use: AtomicBool,
+  readers: AtomicUsize,
+  /// Held by lends and reclaims for the few steps in which they update the
+  /// pointer and the finalizer together. Borrows that find it set retry
+  /// instead of reporting the cell as borrowed.
+  busy: AtomicBool,
+  uid: AtomicU64,
+  /// Only accessed while holding `busy`.
+  finalizer: UnsafeCell<Option<Finalizer>>,
+  _marker: PhantomData<*mut T>,
+  #[cfg(feature = "borrow-tracking")]
+  was_borrowed: AtomicBool,
+  #[cfg(feature = "contention-hook")]
+  contention_hook: OnceLock<Box<dyn Fn() + Send + Sync>>,
 }
 
 impl<T: Sync> FfiCell<T> {
@@ -21,9 +79,37 @@ impl<T: Sync> FfiCell<T> {
     Self {
       ptr: AtomicPtr::new(null_mut()),
       in_use: AtomicBool::new(false),
+      readers: AtomicUsize::new(0),
+      busy: AtomicBool::new(false),
+      uid: AtomicU64::new(0),
+      finalizer: UnsafeCell::new(None),
+      _marker: PhantomData,
+      #[cfg(feature = "borrow-tracking")]
+      was_borrowed: AtomicBool::new(false),
+      #[cfg(feature = "contention-hook")]
+      contention_hook: OnceLock::new(),
     }
   }
 
+  /// Registers a hook that is called on the failing thread whenever a
+  /// borrow fails because the value is already borrowed.
+  #[cfg(feature = "contention-hook")]
+  #[track_caller]
+  pub fn on_contention(&self, hook: impl Fn() + Send + Sync + 'static) {
+    self.try_on_contention(hook).unwrap_or_display_err()
+  }
+
+  #[cfg(feature = "contention-hook")]
+  pub fn try_on_contention(
+    &self,
+    hook: impl Fn() + Send + Sync + 'static,
+  ) -> Result<(), HookError> {
+    self
+      .contention_hook
+      .set(Box::new(hook))
+      .map_err(|_| HookError::AlreadySet)
+  }
+
   #[track_caller]
   pub fn run<R>(&self, object: &mut T, f: impl FnOnce() -> R) -> R {
     self.try_run(object, f).unwrap_or_display_err()
@@ -41,6 +127,47 @@ impl<T: Sync> FfiCell<T> {
     Ok(f())
   }
 
+  #[track_caller]
+  pub fn run_with_finalizer<R>(
+    &self,
+    object: &mut T,
+    finalizer: impl FnOnce(&mut T) + Send + 'static,
+    f: impl FnOnce() -> R,
+  ) -> R {
+    self
+      .try_run_with_finalizer(object, finalizer, f)
+      .unwrap_or_display_err()
+  }
+
+  /// Same as [`try_run`](Self::try_run), but runs `finalizer` on the object
+  /// when it is reclaimed.
+  pub fn try_run_with_finalizer<R>(
+    &self,
+    object: &mut T,
+    finalizer: impl FnOnce(&mut T) + Send + 'static,
+    f: impl FnOnce() -> R,
+  ) -> Result<R, Error> {
+    unsafe {
+      self.try_lend_with_finalizer(object, finalizer)?;
+    }
+    let _reclaim = ScopeGuard::new(|| self.reclaim());
+    Ok(f())
+  }
+
+  /// Same as [`try_run`](Self::try_run), but hands `object` back if it
+  /// could not be lent, so it can be offered to another cell.
+  pub fn try_run_recoverable<'o, R>(
+    &self,
+    object: &'o mut T,
+    f: impl FnOnce() -> R,
+  ) -> Result<R, (LendError, &'o mut T)> {
+    if let Err(err) = unsafe { self.try_lend(object) } {
+      return Err((err, object));
+    }
+    let _reclaim = ScopeGuard::new(|| self.reclaim());
+    Ok(f())
+  }
+
   /// # Safety
   /// The object pointed to in the params cannot be referenced until
   /// `reclaim` is called without panicking or `try_reclaim` is called and
@@ -72,55 +199,574 @@ impl<T: Sync> FfiCell<T> {
     }
   }
 
+  /// # Safety
+  /// Same as [`lend`](Self::lend).
+  #[track_caller]
+  pub unsafe fn lend_with_finalizer(
+    &self,
+    ptr: &mut T,
+    finalizer: impl FnOnce(&mut T) + Send + 'static,
+  ) {
+    unsafe {
+      self
+        .try_lend_with_finalizer(ptr, finalizer)
+        .unwrap_or_display_err()
+    }
+  }
+
+  /// Lends `ptr` and arranges for `finalizer` to run on it when the loan
+  /// is reclaimed, by whichever path reclaims it.
+  ///
+  /// # Safety
+  /// Same as [`try_lend`](Self::try_lend).
+  pub unsafe fn try_lend_with_finalizer(
+    &self,
+    ptr: &mut T,
+    finalizer: impl FnOnce(&mut T) + Send + 'static,
+  ) -> Result<(), LendError> {
+    // Reclaims hold the same flag while emptying the cell, so they see the
+    // pointer and its finalizer stored as a single step.
+    let _busy = self.hold_busy();
+    if self.in_use.load(Ordering::SeqCst) {
+      return Err(LendError::AlreadyLent);
+    }
+    match self.ptr.compare_exchange(
+      null_mut(),
+      ptr,
+      Ordering::SeqCst,
+      Ordering::SeqCst,
+    ) {
+      Ok(_) => {
+        unsafe { *self.finalizer.get() = Some(Finalizer::new(finalizer)) };
+        Ok(())
+      },
+      Err(_) => Err(LendError::AlreadyHasLoan),
+    }
+  }
+
+  /// Same as [`try_lend`](Self::try_lend), for callers that do not care why
+  /// the lend failed.
+  ///
+  /// # Safety
+  /// Same as [`try_lend`](Self::try_lend).
+  pub unsafe fn lend_ok(&self, ptr: &mut T) -> Option<()> {
+    unsafe { self.try_lend(ptr).ok() }
+  }
+
+  /// Lends `object` for as long as the returned receipt lives. Dropping the
+  /// receipt reclaims it, waiting for or aborting on a borrow that is still
+  /// live according to `policy`.
+  ///
+  /// # Safety
+  /// The receipt must not be leaked.
   #[track_caller]
-  pub fn borrow(&self) -> impl DerefMut<Target = T> {
+  pub unsafe fn lend_receipt<'a>(
+    &'a self,
+    object: &'a mut T,
+    policy: ReceiptPolicy,
+  ) -> LoanReceipt<'a, T> {
+    unsafe {
+      self
+        .try_lend_receipt(object, policy)
+        .unwrap_or_display_err()
+    }
+  }
+
+  /// # Safety
+  /// Same as [`lend_receipt`](Self::lend_receipt).
+  pub unsafe fn try_lend_receipt<'a>(
+    &'a self,
+    object: &'a mut T,
+    policy: ReceiptPolicy,
+  ) -> Result<LoanReceipt<'a, T>, LendError> {
+    unsafe { self.try_lend(object)? };
+    Ok(LoanReceipt {
+      cell: self,
+      policy,
+      _marker: PhantomData,
+    })
+  }
+
+  /// Lends `object` and returns a token that tracks the loan's lifecycle in
+  /// its type. See [`Lent`].
+  ///
+  /// # Safety
+  /// The token, and any [`Borrowed`] made from it, must not be leaked. The
+  /// value must not be borrowed through the cell's other methods when the
+  /// token is borrowed; the panic only reports that this requirement was
+  /// violated.
+  #[track_caller]
+  pub unsafe fn lend_typed<'a>(&'a self, object: &'a mut T) -> Lent<'a, T> {
+    unsafe { self.try_lend_typed(object).unwrap_or_display_err() }
+  }
+
+  /// # Safety
+  /// Same as [`lend_typed`](Self::lend_typed).
+  pub unsafe fn try_lend_typed<'a>(
+    &'a self,
+    object: &'a mut T,
+  ) -> Result<Lent<'a, T>, LendError> {
+    unsafe { self.try_lend(object)? };
+    Ok(Lent { cell: self, _marker: PhantomData })
+  }
+
+  #[track_caller]
+  pub fn borrow(&self) -> FfiGuard<'_, T> {
     self.try_borrow().unwrap_or_display_err()
   }
 
-  pub fn try_borrow(&self) -> Result<impl DerefMut<Target = T>, BorrowError> {
-    if self.in_use.swap(true, Ordering::SeqCst) {
-      Err(BorrowError::AlreadyBorrowed)
-    } else {
-      let ptr = self.ptr.swap(null_mut(), Ordering::SeqCst);
-      match NonNull::new(ptr) {
-        Some(ptr) => Ok(FfiGuard {
-          ptr,
-          cell: self,
-          _marker: PhantomData,
-        }),
-        None => Err(BorrowError::Unavailable),
+  pub fn try_borrow(&self) -> Result<FfiGuard<'_, T>, BorrowError> {
+    let guard = self.acquire()?;
+    #[cfg(feature = "borrow-tracking")]
+    self.was_borrowed.store(true, Ordering::SeqCst);
+    Ok(guard)
+  }
+
+  /// Takes the exclusive borrow without counting it as handed out, for
+  /// callers that may still refuse it.
+  fn acquire(&self) -> Result<FfiGuard<'_, T>, BorrowError> {
+    // Setting the in-use flag before checking the busy flag, while lends
+    // and reclaims do the opposite, guarantees that at least one side sees
+    // the other.
+    loop {
+      if self.in_use.swap(true, Ordering::SeqCst) {
+        return Err(self.contended());
       }
+      if !self.busy.load(Ordering::SeqCst) {
+        break;
+      }
+      self.in_use.store(false, Ordering::SeqCst);
+      thread::yield_now();
+    }
+    if self.readers.load(Ordering::SeqCst) > 0 {
+      self.in_use.store(false, Ordering::SeqCst);
+      return Err(self.contended());
+    }
+
+    let ptr = self.ptr.swap(null_mut(), Ordering::SeqCst);
+    match NonNull::new(ptr) {
+      Some(ptr) => Ok(FfiGuard {
+        ptr,
+        cell: self,
+        on_drop: Vec::new(),
+        _marker: PhantomData,
+      }),
+      None => {
+        self.in_use.store(false, Ordering::SeqCst);
+        Err(BorrowError::Unavailable)
+      },
     }
   }
 
+  #[track_caller]
+  pub fn borrow_shared(&self) -> SharedGuard<'_, T> {
+    self.try_borrow_shared().unwrap_or_display_err()
+  }
+
+  /// Borrows the value immutably. Any number of shared borrows can be held
+  /// at once, but not together with an exclusive one.
+  ///
+  /// Since `T: Sync`, this is enough for types that handle their own
+  /// synchronization, such as ones built from atomics or mutexes, to be used
+  /// by concurrent ffi callbacks.
+  pub fn try_borrow_shared(&self) -> Result<SharedGuard<'_, T>, BorrowError> {
+    // Registering as a reader before checking the in-use and busy flags,
+    // while exclusive borrows and reclaims do the opposite, guarantees that
+    // at least one side sees the other.
+    loop {
+      self.readers.fetch_add(1, Ordering::SeqCst);
+      if self.in_use.load(Ordering::SeqCst) {
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+        return Err(self.contended());
+      }
+      if !self.busy.load(Ordering::SeqCst) {
+        break;
+      }
+      self.readers.fetch_sub(1, Ordering::SeqCst);
+      thread::yield_now();
+    }
+
+    match NonNull::new(self.ptr.load(Ordering::SeqCst)) {
+      Some(ptr) => {
+        #[cfg(feature = "borrow-tracking")]
+        self.was_borrowed.store(true, Ordering::SeqCst);
+        Ok(SharedGuard { ptr, cell: self })
+      },
+      None => {
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+        Err(BorrowError::Unavailable)
+      },
+    }
+  }
+
+  fn contended(&self) -> BorrowError {
+    #[cfg(feature = "contention-hook")]
+    if let Some(hook) = self.contention_hook.get() {
+      hook();
+    }
+    BorrowError::AlreadyBorrowed
+  }
+
+  #[track_caller]
+  pub fn detach_borrow(&self) -> DetachedBorrow<'_, T> {
+    self.borrow().detach()
+  }
+
+  pub fn try_detach_borrow(
+    &self,
+  ) -> Result<DetachedBorrow<'_, T>, BorrowError> {
+    self.try_borrow().map(FfiGuard::detach)
+  }
+
+  pub fn borrow_ok(&self) -> Option<FfiGuard<'_, T>> {
+    self.try_borrow().ok()
+  }
+
+  #[track_caller]
+  pub fn borrow_if(&self, cond: impl FnOnce() -> bool) -> FfiGuard<'_, T> {
+    self.try_borrow_if(cond).unwrap_or_display_err()
+  }
+
+  /// Borrows the value only if `cond` returns `true`.
+  ///
+  /// `cond` is evaluated while the borrow is held, so the value cannot be
+  /// borrowed by anyone else between the check and the borrow being granted.
+  pub fn try_borrow_if(
+    &self,
+    cond: impl FnOnce() -> bool,
+  ) -> Result<FfiGuard<'_, T>, BorrowError> {
+    let guard = self.acquire()?;
+    if cond() {
+      #[cfg(feature = "borrow-tracking")]
+      self.was_borrowed.store(true, Ordering::SeqCst);
+      Ok(guard)
+    } else {
+      Err(BorrowError::ConditionFailed)
+    }
+  }
+
+  /// Borrows the value and runs `apply` on it. If `apply` fails or panics,
+  /// the value is restored from the snapshot taken beforehand.
+  #[track_caller]
+  pub fn borrow_transact<S, R, E>(
+    &self,
+    snapshot: impl FnOnce(&T) -> S,
+    apply: impl FnOnce(&mut T) -> Result<R, E>,
+    rollback: impl FnOnce(&mut T, S),
+  ) -> Result<R, E> {
+    self
+      .try_borrow_transact(snapshot, apply, rollback)
+      .unwrap_or_display_err()
+  }
+
+  pub fn try_borrow_transact<S, R, E>(
+    &self,
+    snapshot: impl FnOnce(&T) -> S,
+    apply: impl FnOnce(&mut T) -> Result<R, E>,
+    rollback: impl FnOnce(&mut T, S),
+  ) -> Result<Result<R, E>, BorrowError> {
+    let mut guard = self.try_borrow()?;
+    let saved = snapshot(&guard);
+    match catch_unwind(AssertUnwindSafe(|| apply(&mut guard))) {
+      Ok(Ok(value)) => Ok(Ok(value)),
+      Ok(Err(err)) => {
+        rollback(&mut guard, saved);
+        Ok(Err(err))
+      },
+      Err(panic) => {
+        rollback(&mut guard, saved);
+        resume_unwind(panic)
+      },
+    }
+  }
+
+  /// Runs `f` on the value if it can be borrowed, or returns `default`
+  /// without panicking if it cannot.
+  pub fn borrow_or<R>(&self, default: R, f: impl FnOnce(&mut T) -> R) -> R {
+    match self.try_borrow() {
+      Ok(mut guard) => f(&mut guard),
+      Err(_) => default,
+    }
+  }
+
+  /// Runs `f` on the value and reports the outcome as a C status code
+  /// without letting a panic unwind out: [`STATUS_OK`] on success,
+  /// [`STATUS_BORROW_FAILED`] if the value could not be borrowed, or
+  /// [`STATUS_PANICKED`] if `f` panicked.
+  pub fn borrow_to_c_status(&self, f: impl FnOnce(&mut T)) -> c_int {
+    let Ok(mut guard) = self.try_borrow() else {
+      return STATUS_BORROW_FAILED;
+    };
+    match catch_unwind(AssertUnwindSafe(|| f(&mut guard))) {
+      Ok(()) => STATUS_OK,
+      Err(_) => STATUS_PANICKED,
+    }
+  }
+
+  /// Same as [`borrow`](Self::borrow), but spells out that a borrow from a
+  /// `static` cell yields a guard that can be stored for as long as needed.
+  #[track_caller]
+  pub fn borrow_static(&'static self) -> FfiGuard<'static, T> {
+    self.borrow()
+  }
+
+  pub fn try_borrow_static(
+    &'static self,
+  ) -> Result<FfiGuard<'static, T>, BorrowError> {
+    self.try_borrow()
+  }
+
   #[track_caller]
   pub fn reclaim(&self) {
     self.try_reclaim().unwrap_or_display_err()
   }
 
   pub fn try_reclaim(&self) -> Result<(), ReclaimError> {
-    if self.in_use.load(Ordering::SeqCst) {
-      Err(ReclaimError::InUse)
-    } else if self.ptr.swap(null_mut(), Ordering::SeqCst).is_null() {
-      unreachable!("missing pointer when not in use")
-    } else {
-      Ok(())
+    let (ptr, finalizer) =
+      self.take_lent()?.ok_or(ReclaimError::Unavailable)?;
+    Self::finalize(ptr, finalizer);
+    Ok(())
+  }
+
+  pub fn reclaim_ok(&self) -> Option<()> {
+    self.try_reclaim().ok()
+  }
+
+  /// Returns `true` if the value has been borrowed since the cell was
+  /// created or since the flag was last cleared.
+  #[cfg(feature = "borrow-tracking")]
+  pub fn was_borrowed(&self) -> bool {
+    self.was_borrowed.load(Ordering::SeqCst)
+  }
+
+  #[cfg(feature = "borrow-tracking")]
+  pub fn reset_borrowed_flag(&self) {
+    self.was_borrowed.store(false, Ordering::SeqCst);
+  }
+
+  /// Returns an identifier that is unique among all cells in the process
+  /// and, unlike the cell's address, stays the same when the cell is moved.
+  ///
+  /// The identifier is assigned on first use so that `new` can stay `const`.
+  pub fn uid(&self) -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    let uid = self.uid.load(Ordering::SeqCst);
+    if uid != 0 {
+      return uid;
     }
+    let next = NEXT.fetch_add(1, Ordering::Relaxed);
+    match self
+      .uid
+      .compare_exchange(0, next, Ordering::SeqCst, Ordering::SeqCst)
+    {
+      Ok(_) => next,
+      Err(uid) => uid,
+    }
+  }
+
+  /// Views this cell as a cell of a layout-identical type, for example the
+  /// field of a `#[repr(transparent)]` newtype. Both views share the same
+  /// loan and borrow state.
+  ///
+  /// # Safety
+  /// `T` and `U` must have the same size and alignment, and every valid `T`
+  /// must be a valid `U` and vice versa, since values written through
+  /// either view are read back through the other. `U` must also be [`Send`]
+  /// if `T` is, since the cell can then be shared with other threads, which
+  /// could borrow the value as `&mut U` through this view.
+  pub unsafe fn as_inner_cell<U: Sync>(&self) -> &FfiCell<U> {
+    unsafe { &*(self as *const Self).cast::<FfiCell<U>>() }
+  }
+
+  /// Empties the cell and clears its in-use flag unconditionally.
+  ///
+  /// Unlike [`reset`](Self::reset), this does not fail if the flag reads as
+  /// in use. That is the case after a guard was leaked, for example with
+  /// [`mem::forget`](std::mem::forget), since its borrow then never ends.
+  ///
+  /// # Safety
+  /// No guard borrowed from this cell, exclusive or shared, may be alive,
+  /// and none may be created concurrently with this call. A guard that
+  /// outlives this call would alias whatever is lent next, and its drop
+  /// would fail. No other call may lend or reclaim concurrently either.
+  pub unsafe fn force_reclaim(&self) {
+    self.ptr.store(null_mut(), Ordering::SeqCst);
+    unsafe { *self.finalizer.get() = None };
+    self.in_use.store(false, Ordering::SeqCst);
+    self.readers.store(0, Ordering::SeqCst);
+    self.busy.store(false, Ordering::SeqCst);
+  }
+
+  /// Returns a human readable report of the cell's state, suitable for
+  /// including in bug reports.
+  pub fn diagnostic_dump(&self) -> String {
+    let uid = match self.uid.load(Ordering::SeqCst) {
+      0 => "unassigned".to_string(),
+      uid => uid.to_string(),
+    };
+    #[cfg_attr(not(feature = "borrow-tracking"), allow(unused_mut))]
+    let mut dump = format!(
+      "ffi-cell<{}> at {:p}\n  uid: {}\n  pointer: {:p}\n  in use: {}\n  \
+       readers: {}\n",
+      type_name::<T>(),
+      self,
+      uid,
+      self.ptr.load(Ordering::SeqCst),
+      self.in_use.load(Ordering::SeqCst),
+      self.readers.load(Ordering::SeqCst),
+    );
+    #[cfg(feature = "borrow-tracking")]
+    dump.push_str(&format!("  was borrowed: {}\n", self.was_borrowed()));
+    dump
+  }
+
+  /// Returns the cell to the state it was in when it was constructed,
+  /// reclaiming the lent value if there is one.
+  ///
+  /// This is intended for cells that are reused across independent ffi
+  /// sessions and must only be called between sessions, when no foreign
+  /// code can still reach the cell.
+  pub fn reset(&self) -> Result<(), ReclaimError> {
+    if let Some((ptr, finalizer)) = self.take_lent()? {
+      Self::finalize(ptr, finalizer);
+    }
+    #[cfg(feature = "borrow-tracking")]
+    self.reset_borrowed_flag();
+    fence(Ordering::SeqCst);
+    Ok(())
+  }
+
+  /// Empties the cell for reclaiming, along with the loan's finalizer. The
+  /// busy flag is held while doing so, which keeps borrows from picking up
+  /// the pointer and a lend from replacing the finalizer meanwhile, without
+  /// making the cell look borrowed to them.
+  fn take_lent(&self) -> Result<Option<Loan<T>>, ReclaimError> {
+    let _busy = self.hold_busy();
+    if self.in_use.load(Ordering::SeqCst)
+      || self.readers.load(Ordering::SeqCst) > 0
+    {
+      return Err(ReclaimError::InUse);
+    }
+    let ptr = NonNull::new(self.ptr.swap(null_mut(), Ordering::SeqCst));
+    Ok(ptr.map(|ptr| (ptr, unsafe { (*self.finalizer.get()).take() })))
+  }
+
+  fn hold_busy(&self) -> ScopeGuard<impl FnMut() + '_> {
+    while self.busy.swap(true, Ordering::SeqCst) {
+      thread::yield_now();
+    }
+    ScopeGuard::new(|| self.busy.store(false, Ordering::SeqCst))
+  }
+
+  fn finalize(mut ptr: NonNull<T>, finalizer: Option<Finalizer>) {
+    if let Some(finalizer) = finalizer {
+      unsafe { finalizer.call(ptr.as_mut()) };
+    }
+  }
+}
+
+/// A boxed `FnOnce(&mut T)` with `T` erased from its type. Storing it as
+/// `Box<dyn FnOnce(&mut T)>` would make the drop checker require every lent
+/// `T` to outlive the cell, even for cells that never use a finalizer.
+struct Finalizer {
+  data: NonNull<()>,
+  call: unsafe fn(NonNull<()>, NonNull<()>),
+  drop: unsafe fn(NonNull<()>),
+}
+
+/// A lent pointer taken out of a cell, with the finalizer to run on it.
+type Loan<T> = (NonNull<T>, Option<Finalizer>);
+
+// Only constructed from `Send` closures.
+unsafe impl Send for Finalizer {}
+
+impl Finalizer {
+  fn new<T, F: FnOnce(&mut T) + Send + 'static>(f: F) -> Self {
+    unsafe fn call<T, F: FnOnce(&mut T)>(data: NonNull<()>, ptr: NonNull<()>) {
+      let f = unsafe { Box::from_raw(data.cast::<F>().as_ptr()) };
+      f(unsafe { ptr.cast::<T>().as_mut() });
+    }
+
+    unsafe fn drop<F>(data: NonNull<()>) {
+      unsafe { std::mem::drop(Box::from_raw(data.cast::<F>().as_ptr())) };
+    }
+
+    Self {
+      data: NonNull::from(Box::leak(Box::new(f))).cast(),
+      call: call::<T, F>,
+      drop: drop::<F>,
+    }
+  }
+
+  /// # Safety
+  /// The finalizer must have been created for the same `T`.
+  unsafe fn call<T>(self, value: &mut T) {
+    let this = ManuallyDrop::new(self);
+    unsafe { (this.call)(this.data, NonNull::from(value).cast()) }
+  }
+}
+
+impl Drop for Finalizer {
+  fn drop(&mut self) {
+    unsafe { (self.drop)(self.data) }
   }
 }
 
+// Any thread with access to the cell can borrow the lent `&mut T`, so moving
+// or sharing the cell across threads also moves the value's exclusive access.
+unsafe impl<T: Sync + Send> Send for FfiCell<T> {}
+unsafe impl<T: Sync + Send> Sync for FfiCell<T> {}
+
 impl<T: Sync> Default for FfiCell<T> {
   fn default() -> Self {
     Self::new()
   }
 }
 
-struct FfiGuard<'g, T: Sync> {
+/// Exclusive access to a value borrowed from an [`FfiCell`].
+///
+/// Like `&mut T`, the guard is [`Send`] only if `T` is:
+///
+/// ```compile_fail
+/// # use ffi_cell::FfiGuard;
+/// # use std::sync::MutexGuard;
+/// fn assert_send<T: Send>() {}
+/// assert_send::<FfiGuard<'static, MutexGuard<'static, ()>>>();
+/// ```
+pub struct FfiGuard<'g, T: Sync> {
   ptr: NonNull<T>,
   cell: &'g FfiCell<T>,
+  on_drop: Vec<Box<dyn FnOnce() + Send>>,
   _marker: PhantomData<&'g ()>,
 }
 
+// The guard stands in for the `&mut T` it hands out. Its cleanup closures
+// are `Send` and are never reachable through a shared reference.
+unsafe impl<'g, T: Sync + Send> Send for FfiGuard<'g, T> {}
+unsafe impl<'g, T: Sync> Sync for FfiGuard<'g, T> {}
+
+impl<'g, T: Sync> FfiGuard<'g, T> {
+  /// Converts the guard into a handle that keeps the cell borrowed without
+  /// giving access to the value, so that the borrow can outlive the current
+  /// ffi callback.
+  pub fn detach(self) -> DetachedBorrow<'g, T> {
+    DetachedBorrow { guard: self }
+  }
+
+  /// Binds the borrowed value to `f` so that C can call back into it for as
+  /// long as the borrow lasts.
+  pub fn into_trampoline<F: FnMut(&mut T)>(self, f: F) -> Trampoline<'g, T, F> {
+    Trampoline::new(self, f)
+  }
+
+  /// Registers a closure to run when the guard is dropped, after the value
+  /// has been returned to the cell. Closures run in reverse order of
+  /// registration, including when the guard is dropped during unwinding.
+  pub fn on_drop(&mut self, f: impl FnOnce() + Send + 'static) {
+    self.on_drop.push(Box::new(f));
+  }
+}
+
 impl<'g, T: Sync> Deref for FfiGuard<'g, T> {
   type Target = T;
 
@@ -135,20 +781,97 @@ impl<'g, T: Sync> DerefMut for FfiGuard<'g, T> {
   }
 }
 
+impl<'g, T: Sync + Read> Read for FfiGuard<'g, T> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    (**self).read(buf)
+  }
+}
+
+impl<'g, T: Sync + Write> Write for FfiGuard<'g, T> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    (**self).write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    (**self).flush()
+  }
+}
+
 impl<'g, T: Sync> Drop for FfiGuard<'g, T> {
   fn drop(&mut self) {
-    self
-      .cell
-      .ptr
-      .compare_exchange(
-        null_mut(),
-        self.ptr.as_ptr(),
-        Ordering::SeqCst,
-        Ordering::SeqCst,
-      )
-      .expect("tried to return lent pointer, but another pointer was there");
+    if let Err(found) = self.cell.ptr.compare_exchange(
+      null_mut(),
+      self.ptr.as_ptr(),
+      Ordering::SeqCst,
+      Ordering::SeqCst,
+    ) {
+      panic!(
+        "ffi-cell at {:p} was modified while borrowed: tried to return \
+         borrowed pointer {:p}, but the cell already held {found:p}",
+        self.cell, self.ptr
+      );
+    }
     let was_in_use = self.cell.in_use.swap(false, Ordering::SeqCst);
-    assert!(was_in_use, "object was not in use when it was returned");
+    assert!(
+      was_in_use,
+      "ffi-cell at {:p} was cleared while borrowed: returned pointer {:p} to \
+       a cell that was no longer marked in use",
+      self.cell, self.ptr
+    );
+    while let Some(f) = self.on_drop.pop() {
+      f();
+    }
+  }
+}
+
+/// Shared access to a value borrowed from an [`FfiCell`].
+pub struct SharedGuard<'g, T: Sync> {
+  ptr: NonNull<T>,
+  cell: &'g FfiCell<T>,
+}
+
+// The guard stands in for the `&T` it hands out.
+unsafe impl<'g, T: Sync> Send for SharedGuard<'g, T> {}
+unsafe impl<'g, T: Sync> Sync for SharedGuard<'g, T> {}
+
+impl<'g, T: Sync> Deref for SharedGuard<'g, T> {
+  type Target = T;
+
+  fn deref(&self) -> &Self::Target {
+    unsafe { self.ptr.as_ref() }
+  }
+}
+
+impl<'g, T: Sync> Drop for SharedGuard<'g, T> {
+  fn drop(&mut self) {
+    self.cell.readers.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+/// A borrow that is held across ffi calls without access to the value.
+///
+/// The cell stays borrowed until the handle is reattached and the resulting
+/// guard is dropped, or until the handle itself is dropped.
+pub struct DetachedBorrow<'g, T: Sync> {
+  guard: FfiGuard<'g, T>,
+}
+
+impl<'g, T: Sync> DetachedBorrow<'g, T> {
+  pub fn reattach(self) -> FfiGuard<'g, T> {
+    self.guard
+  }
+
+  /// Converts the handle into a pointer that can be stored as ffi user data.
+  pub fn into_raw(self) -> *mut c_void {
+    Box::into_raw(Box::new(self)).cast()
+  }
+
+  /// # Safety
+  /// `ptr` must have been returned by [`into_raw`](Self::into_raw) for a
+  /// handle of the same type, must not have been passed to this function
+  /// before, and `'g` must not outlive the cell it was borrowed from.
+  pub unsafe fn from_raw(ptr: *mut c_void) -> Self {
+    unsafe { *Box::from_raw(ptr.cast()) }
   }
 }
 
@@ -167,6 +890,10 @@ pub enum LendError {
   AlreadyLent,
   #[display("it already has one")]
   AlreadyHasLoan,
+  #[display("it was lent from another thread")]
+  WrongThread,
+  #[display("it has no free slots")]
+  Full,
 }
 
 #[non_exhaustive]
@@ -177,6 +904,10 @@ pub enum BorrowError {
   Unavailable,
   #[display("the cell's value is already lent out")]
   AlreadyBorrowed,
+  #[display("the borrow condition did not hold")]
+  ConditionFailed,
+  #[display("the cell's value was lent from another thread")]
+  WrongThread,
 }
 
 #[non_exhaustive]
@@ -185,6 +916,19 @@ pub enum BorrowError {
 pub enum ReclaimError {
   #[display("it is currently in use")]
   InUse,
+  #[display("it does not have a value")]
+  Unavailable,
+  #[display("it was lent from another thread")]
+  WrongThread,
+}
+
+#[cfg(feature = "contention-hook")]
+#[non_exhaustive]
+#[derive(Debug, Display, Error)]
+#[display("cannot register hook on ffi-cell because {_variant}")]
+pub enum HookError {
+  #[display("it already has one")]
+  AlreadySet,
 }
 
 struct ScopeGuard<F: FnMut()>(F);
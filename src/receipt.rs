@@ -0,0 +1,51 @@
+use std::{ffi::c_void, marker::PhantomData, process, thread};
+
+use crate::{FfiCell, ReclaimError};
+
+/// What a [`LoanReceipt`] does when it is dropped while the lent value is
+/// still borrowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiptPolicy {
+  /// Abort the process, since unwinding would free the value while it is
+  /// still borrowed.
+  Abort,
+  /// Yield until the borrow is released, then reclaim the value. This never
+  /// returns if the borrow is held by the thread dropping the receipt.
+  Block,
+}
+
+/// Ties a loan to the lifetime of the lent reference. Dropping the receipt
+/// reclaims the value.
+pub struct LoanReceipt<'a, T: Sync> {
+  pub(crate) cell: &'a FfiCell<T>,
+  pub(crate) policy: ReceiptPolicy,
+  pub(crate) _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: Sync> LoanReceipt<'a, T> {
+  /// Returns a pointer to the cell, to be registered with C as user data.
+  pub fn user_data(&self) -> *mut c_void {
+    (self.cell as *const FfiCell<T>).cast_mut().cast()
+  }
+}
+
+impl<'a, T: Sync> Drop for LoanReceipt<'a, T> {
+  fn drop(&mut self) {
+    loop {
+      match self.cell.try_reclaim() {
+        Ok(()) | Err(ReclaimError::Unavailable) => return,
+        Err(ReclaimError::InUse) if self.policy == ReceiptPolicy::Block => {
+          thread::yield_now()
+        },
+        Err(err) => abort_reclaim(err),
+      }
+    }
+  }
+}
+
+/// Aborts when a loan's owner is dropped but the value cannot be reclaimed,
+/// since unwinding would free the value while it is still borrowed.
+pub(crate) fn abort_reclaim(err: ReclaimError) -> ! {
+  eprintln!("{err}; aborting to avoid freeing a borrowed value");
+  process::abort()
+}
@@ -90,3 +90,1381 @@ fn test() {
     "cell should not be in use after run is complete"
   );
 }
+
+#[test]
+#[should_panic(expected = "was modified while borrowed")]
+fn guard_drop_reports_replaced_pointer() {
+  let cell = FfiCell::<i32>::new();
+  let mut value = 1;
+  let mut other = 2;
+  unsafe {
+    cell.lend(&mut value);
+  }
+  let guard = cell.borrow();
+  cell.ptr.store(&mut other, Ordering::SeqCst);
+  drop(guard);
+}
+
+#[test]
+#[should_panic(expected = "was cleared while borrowed")]
+fn guard_drop_reports_cleared_borrow() {
+  let cell = FfiCell::<i32>::new();
+  let mut value = 1;
+  unsafe {
+    cell.lend(&mut value);
+  }
+  let guard = cell.borrow();
+  cell.in_use.store(false, Ordering::SeqCst);
+  drop(guard);
+}
+
+#[test]
+fn reset() {
+  let cell = FfiCell::<i32>::new();
+  cell.reset().expect("resetting a fresh cell should succeed");
+
+  let mut value = 42;
+  unsafe {
+    cell.lend(&mut value);
+  }
+  let guard = cell.borrow();
+  assert!(
+    matches!(cell.reset(), Err(ReclaimError::InUse)),
+    "cell should not reset while borrowed"
+  );
+  drop(guard);
+
+  cell.reset().expect("resetting a lent cell should succeed");
+  assert!(
+    cell.ptr.load(Ordering::SeqCst).is_null(),
+    "cell should have null pointer after reset"
+  );
+  assert!(
+    !cell.in_use.load(Ordering::SeqCst),
+    "cell should not be in use after reset"
+  );
+
+  let mut other = 7;
+  cell.run(&mut other, || assert_eq!(*cell.borrow(), 7));
+}
+
+#[test]
+fn pinned() {
+  use std::{marker::PhantomPinned, pin::pin};
+
+  struct Context {
+    cell: FfiCell<i32>,
+    _pinned: PhantomPinned,
+  }
+
+  let context = pin!(Context {
+    cell: FfiCell::new(),
+    _pinned: PhantomPinned,
+  });
+  let context = context.into_ref();
+
+  let mut value = 1;
+  context.cell.run(&mut value, || {
+    *context.cell.borrow() += 1;
+  });
+  assert_eq!(value, 2, "borrow through pin should mutate the lent value");
+}
+
+#[test]
+fn borrow_empty_cell() {
+  let cell = FfiCell::<i32>::new();
+  assert!(
+    matches!(cell.try_borrow(), Err(BorrowError::Unavailable)),
+    "empty cell should have nothing to borrow"
+  );
+  assert!(
+    !cell.in_use.load(Ordering::SeqCst),
+    "failed borrow should not leave the cell in use"
+  );
+
+  let mut value = 42;
+  cell.run(&mut value, || assert_eq!(*cell.borrow(), 42));
+}
+
+#[test]
+fn borrow_static() {
+  use std::cell::RefCell;
+
+  static CELL: FfiCell<i32> = FfiCell::new();
+  thread_local! {
+    static HELD: RefCell<Option<FfiGuard<'static, i32>>> =
+      const { RefCell::new(None) };
+  }
+
+  let mut value = 1;
+  unsafe {
+    CELL.lend(&mut value);
+  }
+  HELD.with_borrow_mut(|held| *held = Some(CELL.borrow_static()));
+  assert!(
+    matches!(CELL.try_borrow_static(), Err(BorrowError::AlreadyBorrowed)),
+    "stored guard should keep the cell borrowed"
+  );
+
+  HELD.with_borrow_mut(|held| {
+    let mut guard = held.take().expect("guard should have been stored");
+    *guard += 1;
+  });
+  CELL.reclaim();
+  assert_eq!(value, 2, "stored guard should mutate the lent value");
+}
+
+#[test]
+fn diagnostic_dump() {
+  let cell = FfiCell::<i32>::new();
+  let dump = cell.diagnostic_dump();
+  assert!(
+    dump.starts_with(&format!("ffi-cell<i32> at {:p}\n", &cell)),
+    "dump should identify the cell: {dump}"
+  );
+  assert!(dump.contains("pointer: 0x0\n"), "dump: {dump}");
+  assert!(dump.contains("in use: false\n"), "dump: {dump}");
+  assert!(dump.contains("uid: unassigned\n"), "dump: {dump}");
+  assert_eq!(
+    cell.uid.load(Ordering::SeqCst),
+    0,
+    "dumping should not assign a uid"
+  );
+
+  let mut value = 42;
+  let value_ptr: *const i32 = &value;
+  unsafe {
+    cell.lend(&mut value);
+  }
+  let dump = cell.diagnostic_dump();
+  assert!(
+    dump.contains(&format!("pointer: {value_ptr:p}\n")),
+    "dump should show the lent pointer: {dump}"
+  );
+
+  let guard = cell.borrow();
+  let dump = cell.diagnostic_dump();
+  assert!(dump.contains("pointer: 0x0\n"), "dump: {dump}");
+  assert!(dump.contains("in use: true\n"), "dump: {dump}");
+  drop(guard);
+  cell.reclaim();
+}
+
+#[test]
+fn borrow_if() {
+  use std::sync::atomic::AtomicBool;
+
+  let cell = FfiCell::<i32>::new();
+  let ready = AtomicBool::new(false);
+  let mut value = 1;
+  cell.run(&mut value, || {
+    assert!(
+      matches!(
+        cell.try_borrow_if(|| ready.load(Ordering::SeqCst)),
+        Err(BorrowError::ConditionFailed)
+      ),
+      "borrow should be refused while the condition is false"
+    );
+    assert!(
+      !cell.in_use.load(Ordering::SeqCst),
+      "refused borrow should not leave the cell in use"
+    );
+
+    ready.store(true, Ordering::SeqCst);
+    *cell.borrow_if(|| ready.load(Ordering::SeqCst)) += 1;
+  });
+  assert_eq!(value, 2, "granted borrow should mutate the lent value");
+}
+
+#[test]
+fn as_inner_cell() {
+  #[repr(transparent)]
+  struct Wrapper(i32);
+
+  let cell = FfiCell::<Wrapper>::new();
+  let inner = unsafe { cell.as_inner_cell::<i32>() };
+  let mut value = Wrapper(1);
+  let value_ptr: *const Wrapper = &value;
+
+  cell.run(&mut value, || {
+    let mut num = inner.borrow();
+    assert_eq!(
+      &*num as *const i32,
+      value_ptr.cast(),
+      "inner view should borrow the same memory"
+    );
+    assert!(
+      matches!(cell.try_borrow(), Err(BorrowError::AlreadyBorrowed)),
+      "views should share borrow state"
+    );
+    *num += 1;
+  });
+  assert_eq!(
+    value.0, 2,
+    "write through inner view should reach the wrapper"
+  );
+}
+
+#[test]
+fn force_reclaim() {
+  let cell = FfiCell::<i32>::new();
+  let mut value = 42;
+  unsafe {
+    cell.lend(&mut value);
+  }
+  let guard = cell.borrow();
+  std::mem::forget(guard);
+  assert!(
+    matches!(cell.try_reclaim(), Err(ReclaimError::InUse)),
+    "leaked guard should leave the cell in use"
+  );
+
+  unsafe {
+    cell.force_reclaim();
+  }
+  assert!(
+    cell.ptr.load(Ordering::SeqCst).is_null(),
+    "cell should have null pointer after force reclaim"
+  );
+  assert!(
+    !cell.in_use.load(Ordering::SeqCst),
+    "cell should not be in use after force reclaim"
+  );
+  cell.run(&mut value, || assert_eq!(*cell.borrow(), 42));
+}
+
+#[cfg(feature = "contention-hook")]
+#[test]
+fn contention_hook() {
+  use std::{
+    sync::{Arc, atomic::AtomicUsize},
+    thread,
+  };
+
+  let cell = FfiCell::<i32>::new();
+  let contended = Arc::new(AtomicUsize::new(0));
+  cell.on_contention({
+    let contended = Arc::clone(&contended);
+    move || {
+      contended.fetch_add(1, Ordering::SeqCst);
+    }
+  });
+  assert!(
+    matches!(cell.try_on_contention(|| {}), Err(HookError::AlreadySet)),
+    "second hook should be rejected"
+  );
+
+  let mut value = 42;
+  cell.run(&mut value, || {
+    let guard = cell.borrow();
+    thread::scope(|s| {
+      s.spawn(|| {
+        assert!(
+          matches!(cell.try_borrow(), Err(BorrowError::AlreadyBorrowed)),
+          "second thread should lose the borrow"
+        );
+        assert_eq!(
+          contended.load(Ordering::SeqCst),
+          1,
+          "hook should run before the error is returned"
+        );
+      });
+    });
+    drop(guard);
+    drop(cell.borrow());
+  });
+  assert_eq!(
+    contended.load(Ordering::SeqCst),
+    1,
+    "uncontended borrows should not run the hook"
+  );
+}
+
+#[test]
+fn guard_io() {
+  use std::io::{Cursor, Read, Write};
+
+  let cell = FfiCell::<Cursor<&mut [u8]>>::new();
+  let mut buf = [0u8; 16];
+  let mut cursor = Cursor::new(&mut buf[..]);
+  cell.run(&mut cursor, || {
+    write!(cell.borrow(), "id={}", 42).expect("write should fit the buffer");
+  });
+  assert_eq!(cursor.position(), 5, "write should advance the cursor");
+  assert_eq!(&buf[..5], b"id=42", "bytes should land in the lent buffer");
+
+  let cell = FfiCell::<&[u8]>::new();
+  let mut input = &b"hello"[..];
+  let mut read = [0u8; 3];
+  cell.run(&mut input, || {
+    cell
+      .borrow()
+      .read_exact(&mut read)
+      .expect("input should have 3 bytes");
+  });
+  assert_eq!(&read, b"hel", "read should drain the lent buffer");
+  assert_eq!(input, b"lo", "read should consume from the lent buffer");
+}
+
+#[test]
+fn inline_cell() {
+  let cell = InlineFfiCell::new([0u8; 16]);
+  cell.borrow()[0] = 1;
+  {
+    let mut bytes = cell.borrow();
+    assert!(
+      matches!(cell.try_borrow(), Err(BorrowError::AlreadyBorrowed)),
+      "inline cell should allow one borrow at a time"
+    );
+    bytes[15] = 2;
+  }
+  let bytes = *cell.borrow();
+  assert_eq!(bytes[0], 1, "first write should persist");
+  assert_eq!(bytes[15], 2, "second write should persist");
+  assert_eq!(cell.into_inner(), bytes);
+}
+
+#[cfg(feature = "borrow-tracking")]
+#[test]
+fn was_borrowed() {
+  let cell = FfiCell::<i32>::new();
+  assert!(
+    !cell.was_borrowed(),
+    "new cell should not have been borrowed"
+  );
+
+  let mut value = 42;
+  cell.run(&mut value, || {
+    assert!(!cell.was_borrowed(), "lending should not count as a borrow");
+    drop(cell.borrow());
+  });
+  assert!(
+    cell.was_borrowed(),
+    "flag should stick after the borrow ends"
+  );
+  assert!(
+    cell.diagnostic_dump().contains("was borrowed: true\n"),
+    "dump should include the flag"
+  );
+
+  cell.reset_borrowed_flag();
+  assert!(!cell.was_borrowed(), "flag should be clear after reset");
+  assert!(
+    cell.try_borrow().is_err(),
+    "borrowing an empty cell should fail"
+  );
+  assert!(
+    !cell.was_borrowed(),
+    "failed borrow should not set the flag"
+  );
+  cell.run(&mut value, || {
+    assert!(
+      matches!(
+        cell.try_borrow_if(|| false),
+        Err(BorrowError::ConditionFailed)
+      ),
+      "borrow should be refused"
+    );
+  });
+  assert!(
+    !cell.was_borrowed(),
+    "refused conditional borrow should not set the flag"
+  );
+}
+
+#[test]
+fn ok_variants() {
+  let cell = FfiCell::<i32>::new();
+  let mut value = 1;
+  let mut other = 2;
+  assert!(cell.borrow_ok().is_none(), "empty cell should not borrow");
+
+  assert_eq!(unsafe { cell.lend_ok(&mut value) }, Some(()));
+  assert_eq!(
+    unsafe { cell.lend_ok(&mut other) },
+    None,
+    "cell with a loan should refuse another"
+  );
+
+  let guard = cell.borrow_ok().expect("lent cell should borrow");
+  assert!(
+    cell.borrow_ok().is_none(),
+    "borrowed cell should not borrow"
+  );
+  assert_eq!(cell.reclaim_ok(), None, "borrowed cell should not reclaim");
+  drop(guard);
+
+  assert_eq!(cell.reclaim_ok(), Some(()));
+}
+
+#[test]
+fn detached_borrow() {
+  let cell = FfiCell::<i32>::new();
+  let mut value = 1;
+  cell.run(&mut value, || {
+    let detached = cell.detach_borrow();
+    assert!(
+      cell.in_use.load(Ordering::SeqCst),
+      "detached borrow should keep the cell in use"
+    );
+
+    let user_data = detached.into_raw();
+    assert!(
+      matches!(cell.try_borrow(), Err(BorrowError::AlreadyBorrowed)),
+      "cell should refuse borrows while a detached borrow is stashed"
+    );
+    assert!(
+      matches!(cell.try_reclaim(), Err(ReclaimError::InUse)),
+      "cell should refuse reclaim while a detached borrow is stashed"
+    );
+
+    let detached = unsafe { DetachedBorrow::<i32>::from_raw(user_data) };
+    let mut guard = detached.reattach();
+    *guard += 1;
+    drop(guard);
+    assert!(
+      !cell.in_use.load(Ordering::SeqCst),
+      "dropping the reattached guard should release the borrow"
+    );
+
+    drop(cell.detach_borrow());
+    assert!(
+      !cell.in_use.load(Ordering::SeqCst),
+      "dropping a detached borrow should release it"
+    );
+  });
+  assert_eq!(value, 2, "reattached guard should mutate the lent value");
+}
+
+#[test]
+fn guard_on_drop() {
+  use std::{
+    panic::{AssertUnwindSafe, catch_unwind},
+    sync::Mutex,
+  };
+
+  static CELL: FfiCell<i32> = FfiCell::new();
+  static EVENTS: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+  fn record(event: &'static str) -> impl FnOnce() {
+    move || {
+      assert!(
+        !CELL.in_use.load(Ordering::SeqCst),
+        "cleanup should run after the borrow is released"
+      );
+      EVENTS.lock().unwrap().push(event);
+    }
+  }
+
+  let mut value = 1;
+  CELL.run(&mut value, || {
+    let mut guard = CELL.borrow();
+    guard.on_drop(record("first"));
+    guard.on_drop(record("second"));
+    drop(guard);
+  });
+  assert_eq!(
+    *EVENTS.lock().unwrap(),
+    ["second", "first"],
+    "cleanups should run in reverse order"
+  );
+
+  EVENTS.lock().unwrap().clear();
+  let result = catch_unwind(AssertUnwindSafe(|| {
+    CELL.run(&mut value, || {
+      let mut guard = CELL.borrow();
+      guard.on_drop(record("unwind"));
+      panic!("callback failed");
+    })
+  }));
+  assert!(result.is_err(), "panic should propagate");
+  assert_eq!(
+    *EVENTS.lock().unwrap(),
+    ["unwind"],
+    "cleanup should run during unwinding"
+  );
+}
+
+#[test]
+fn guard_across_threads() {
+  use std::{sync::MutexGuard, thread};
+
+  fn assert_send<T: Send>() {}
+  fn assert_sync<T: Sync>() {}
+  assert_send::<FfiCell<i32>>();
+  assert_sync::<FfiCell<i32>>();
+  assert_send::<FfiGuard<'static, i32>>();
+  assert_sync::<FfiGuard<'static, i32>>();
+  assert_send::<DetachedBorrow<'static, i32>>();
+  assert_sync::<FfiGuard<'static, MutexGuard<'static, ()>>>();
+
+  let cell = FfiCell::<i32>::new();
+  let mut value = 1;
+  cell.run(&mut value, || {
+    let mut guard = cell.borrow();
+    guard.on_drop(|| {});
+    let guard = thread::scope(|s| {
+      s.spawn(move || {
+        *guard += 1;
+        guard
+      })
+      .join()
+      .unwrap()
+    });
+    drop(guard);
+  });
+  assert_eq!(
+    value, 2,
+    "guard moved across threads should mutate the value"
+  );
+}
+
+#[test]
+fn affine_cell() {
+  use std::{cell::Cell, thread};
+
+  let cell = AffineFfiCell::<Cell<i32>>::new();
+  fn assert_send<T: Send>() {}
+  assert_send::<AffineGuard<'static, Cell<i32>>>();
+  let mut value = Cell::new(1);
+  cell.run(&mut value, || {
+    cell.borrow().set(2);
+    thread::scope(|s| {
+      s.spawn(|| {
+        let mut other = Cell::new(0);
+        assert!(
+          matches!(cell.try_borrow(), Err(BorrowError::WrongThread)),
+          "other threads should not borrow"
+        );
+        assert!(
+          matches!(
+            unsafe { cell.try_lend(&mut other) },
+            Err(LendError::WrongThread)
+          ),
+          "other threads should not lend"
+        );
+        assert!(
+          matches!(cell.try_reclaim(), Err(ReclaimError::WrongThread)),
+          "other threads should not reclaim"
+        );
+      });
+    });
+    cell.borrow().set(3);
+  });
+  assert_eq!(value.get(), 3, "lending thread should mutate the value");
+
+  thread::scope(|s| {
+    s.spawn(|| {
+      let mut value = Cell::new(4);
+      cell.run(&mut value, || cell.borrow().set(5));
+      assert_eq!(value.get(), 5, "reclaimed cell should accept a new owner");
+    });
+  });
+}
+
+#[test]
+fn try_run_recoverable() {
+  let primary = FfiCell::<i32>::new();
+  let fallback = FfiCell::<i32>::new();
+  let mut occupant = 0;
+  let mut value = 1;
+  unsafe {
+    primary.lend(&mut occupant);
+  }
+
+  let result = primary
+    .try_run_recoverable(&mut value, || *primary.borrow() += 1)
+    .or_else(|(err, value)| {
+      assert!(
+        matches!(err, LendError::AlreadyHasLoan),
+        "primary cell should already have a loan"
+      );
+      fallback.try_run_recoverable(value, || *fallback.borrow() += 10)
+    });
+  assert!(result.is_ok(), "fallback cell should accept the value");
+  assert_eq!(value, 11, "only the fallback closure should have run");
+
+  primary.reclaim();
+  assert_eq!(occupant, 0, "primary cell's loan should be untouched");
+}
+
+#[test]
+fn reclaim_empty_cell() {
+  let cell = FfiCell::<i32>::new();
+  assert!(
+    matches!(cell.try_reclaim(), Err(ReclaimError::Unavailable)),
+    "empty cell should have nothing to reclaim"
+  );
+
+  let cell = AffineFfiCell::<i32>::new();
+  assert!(
+    matches!(cell.try_reclaim(), Err(ReclaimError::Unavailable)),
+    "empty affine cell should have nothing to reclaim"
+  );
+}
+
+#[test]
+fn arena() {
+  let arena = FfiArena::<i32>::with_capacity(3);
+  let mut values = [10, 20, 30];
+  let mut extra = 40;
+  let [a, b, c] = &mut values;
+
+  let indices = unsafe { [arena.lend(a), arena.lend(b), arena.lend(c)] };
+  assert_eq!(
+    indices,
+    [0, 1, 2],
+    "lends should fill the lowest free slots"
+  );
+  assert!(
+    matches!(unsafe { arena.try_lend(&mut extra) }, Err(LendError::Full)),
+    "full arena should refuse lends"
+  );
+
+  *arena.borrow(1) += 1;
+  let guard = arena.borrow(0);
+  assert!(
+    matches!(arena.try_borrow(0), Err(BorrowError::AlreadyBorrowed)),
+    "each slot should allow one borrow at a time"
+  );
+  assert_eq!(*arena.borrow(2), 30, "other slots should borrow freely");
+  assert!(
+    matches!(arena.try_reclaim(0), Err(ReclaimError::InUse)),
+    "borrowed slot should not reclaim"
+  );
+  drop(guard);
+  assert!(
+    matches!(arena.try_borrow(3), Err(BorrowError::Unavailable)),
+    "out of range index should have nothing to borrow"
+  );
+
+  arena.reclaim(1);
+  assert!(
+    matches!(arena.try_reclaim(1), Err(ReclaimError::Unavailable)),
+    "reclaimed slot should be empty"
+  );
+  assert_eq!(
+    unsafe { arena.lend(&mut extra) },
+    1,
+    "freed index should be reused"
+  );
+  assert_eq!(
+    *arena.borrow(1),
+    40,
+    "reused slot should hold the new value"
+  );
+
+  for index in 0..arena.capacity() {
+    arena.reclaim(index);
+  }
+  assert_eq!(values, [10, 21, 30]);
+}
+
+#[test]
+fn borrow_transact() {
+  let cell = FfiCell::<Vec<i32>>::new();
+  let mut value = vec![1, 2];
+  cell.run(&mut value, || {
+    let result = cell.borrow_transact(
+      Vec::clone,
+      |value| {
+        value.push(3);
+        Err::<(), _>("ffi call failed")
+      },
+      |value, saved| *value = saved,
+    );
+    assert_eq!(result, Err("ffi call failed"));
+    assert_eq!(*cell.borrow(), [1, 2], "failed apply should be rolled back");
+
+    let result = cell.borrow_transact(
+      Vec::len,
+      |value| {
+        value.push(3);
+        Ok::<_, ()>(value.len())
+      },
+      |_, _| panic!("successful apply should not roll back"),
+    );
+    assert_eq!(result, Ok(3));
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+      cell.borrow_transact(
+        Vec::clone,
+        |value| -> Result<(), ()> {
+          value.push(4);
+          panic!("ffi call panicked")
+        },
+        |value, saved| *value = saved,
+      )
+    }));
+    assert!(result.is_err(), "panic in apply should propagate");
+    assert_eq!(
+      *cell.borrow(),
+      [1, 2, 3],
+      "panicking apply should be rolled back"
+    );
+
+    let guard = cell.borrow();
+    assert!(
+      matches!(
+        cell.try_borrow_transact(
+          |_| panic!("should not snapshot"),
+          |_| Ok::<_, ()>(()),
+          |_, ()| {}
+        ),
+        Err(BorrowError::AlreadyBorrowed)
+      ),
+      "try_borrow_transact should report a borrow failure"
+    );
+    drop(guard);
+  });
+  assert_eq!(value, [1, 2, 3], "successful apply should be kept");
+}
+
+#[test]
+fn uid() {
+  let first = FfiCell::<i32>::new();
+  let second = FfiCell::<i32>::new();
+  assert_ne!(first.uid(), second.uid(), "cells should have distinct uids");
+
+  let uid = first.uid();
+  let address: *const _ = &first;
+  let moved = Box::new(first);
+  assert_ne!(&*moved as *const _, address, "cell should have moved");
+  assert_eq!(moved.uid(), uid, "uid should survive the move");
+  assert!(
+    moved.diagnostic_dump().contains(&format!("uid: {uid}\n")),
+    "dump should include the uid"
+  );
+}
+
+#[test]
+fn arena_borrow_many() {
+  let arena = FfiArena::<i32>::with_capacity(4);
+  let mut values = [0, 1, 2, 3];
+  for value in &mut values {
+    unsafe {
+      arena.lend(value);
+    }
+  }
+
+  let mut guards = arena.borrow_many(&[3, 0, 2]);
+  assert_eq!(
+    guards.iter().map(|guard| **guard).collect::<Vec<_>>(),
+    [3, 0, 2],
+    "guards should follow the requested order"
+  );
+  for guard in &mut guards {
+    **guard += 10;
+  }
+
+  assert!(
+    matches!(
+      arena.try_borrow_many(&[1, 2]),
+      Err(BorrowError::AlreadyBorrowed)
+    ),
+    "conflicting multi-borrow should fail"
+  );
+  assert!(
+    arena.try_borrow(1).is_ok(),
+    "failed multi-borrow should release the slots it acquired"
+  );
+  assert!(
+    matches!(
+      arena.try_borrow_many(&[1, 1]),
+      Err(BorrowError::AlreadyBorrowed)
+    ),
+    "duplicate indices should conflict"
+  );
+  drop(guards);
+
+  for index in 0..arena.capacity() {
+    arena.reclaim(index);
+  }
+  assert_eq!(values, [10, 1, 12, 13]);
+}
+
+#[test]
+fn loan_receipt() {
+  use std::{sync::mpsc, thread, time::Duration};
+
+  let cell = FfiCell::<i32>::new();
+  let mut value = 1;
+  let receipt = unsafe { cell.lend_receipt(&mut value, ReceiptPolicy::Abort) };
+  assert_eq!(
+    receipt.user_data(),
+    (&cell as *const FfiCell<i32>).cast_mut().cast()
+  );
+  *cell.borrow() += 1;
+  drop(receipt);
+  assert!(
+    matches!(cell.try_borrow(), Err(BorrowError::Unavailable)),
+    "dropping the receipt should reclaim the value"
+  );
+  assert_eq!(value, 2);
+
+  let mut value = 1;
+  let receipt = unsafe { cell.lend_receipt(&mut value, ReceiptPolicy::Block) };
+  let (borrowed_tx, borrowed_rx) = mpsc::channel();
+  let (release_tx, release_rx) = mpsc::channel();
+  let cell = &cell;
+  thread::scope(|s| {
+    s.spawn(move || {
+      let mut guard = cell.borrow();
+      borrowed_tx.send(()).unwrap();
+      release_rx.recv().unwrap();
+      *guard += 1;
+    });
+    borrowed_rx.recv().unwrap();
+    let dropper = s.spawn(|| drop(receipt));
+    thread::sleep(Duration::from_millis(50));
+    assert!(
+      !dropper.is_finished(),
+      "blocking receipt should wait while the value is borrowed"
+    );
+    release_tx.send(()).unwrap();
+  });
+  assert_eq!(value, 2, "blocking receipt should wait for the borrow");
+  assert!(
+    matches!(cell.try_reclaim(), Err(ReclaimError::Unavailable)),
+    "blocking receipt should reclaim after the borrow ends"
+  );
+}
+
+#[test]
+fn lend_with_finalizer() {
+  use std::sync::{Arc, Mutex};
+
+  let cell = FfiCell::<i32>::new();
+  let finalized = Arc::new(Mutex::new(Vec::new()));
+  let record = |finalized: &Arc<Mutex<Vec<i32>>>| {
+    let finalized = Arc::clone(finalized);
+    move |value: &mut i32| finalized.lock().unwrap().push(*value)
+  };
+
+  let mut value = 1;
+  unsafe {
+    cell.lend_with_finalizer(&mut value, record(&finalized));
+  }
+  *cell.borrow() += 1;
+  assert!(
+    finalized.lock().unwrap().is_empty(),
+    "finalizer should not run before reclaim"
+  );
+  cell.reclaim();
+  assert_eq!(
+    *finalized.lock().unwrap(),
+    [2],
+    "finalizer should see the final value on reclaim"
+  );
+
+  cell.run_with_finalizer(&mut value, record(&finalized), || {
+    *cell.borrow() += 1;
+  });
+  assert_eq!(
+    *finalized.lock().unwrap(),
+    [2, 3],
+    "run should finalize when it reclaims"
+  );
+
+  cell.run(&mut value, || {});
+  assert_eq!(
+    finalized.lock().unwrap().len(),
+    2,
+    "finalizer should only run for its own loan"
+  );
+
+  let mut other = 10;
+  unsafe {
+    cell.lend_with_finalizer(&mut value, record(&finalized));
+    assert!(
+      cell
+        .try_lend_with_finalizer(&mut other, |_| panic!("never lent"))
+        .is_err(),
+      "second loan should be refused"
+    );
+  }
+  cell.reset().unwrap();
+  assert_eq!(
+    *finalized.lock().unwrap(),
+    [2, 3, 3],
+    "reset should run the original loan's finalizer"
+  );
+}
+
+#[test]
+fn trampoline() {
+  use std::ffi::c_void;
+
+  // Stands in for a C function that takes a callback and its user data.
+  unsafe fn c_invoke(cb: unsafe extern "C" fn(*mut c_void), data: *mut c_void) {
+    unsafe {
+      cb(data);
+      cb(data);
+    }
+  }
+
+  let cell = FfiCell::<Vec<i32>>::new();
+  let mut value = vec![1];
+  cell.run(&mut value, || {
+    let mut calls = 0;
+    let mut trampoline = cell.borrow().into_trampoline(|value| {
+      calls += 1;
+      value.push(calls);
+    });
+    let (cb, data) = trampoline.as_c_callback();
+    unsafe { c_invoke(cb, data) };
+    let guard = trampoline.into_guard();
+    assert_eq!(
+      *guard,
+      [1, 1, 2],
+      "callbacks should reach the borrowed value"
+    );
+    assert!(
+      matches!(cell.try_borrow(), Err(BorrowError::AlreadyBorrowed)),
+      "guard should still hold the borrow"
+    );
+  });
+}
+
+#[test]
+fn borrow_shared() {
+  use std::{
+    sync::{Barrier, atomic::AtomicUsize},
+    thread,
+  };
+
+  struct Counter {
+    hits: AtomicUsize,
+  }
+
+  const THREADS: usize = 4;
+  let cell = FfiCell::<Counter>::new();
+  let mut counter = Counter { hits: AtomicUsize::new(0) };
+  cell.run(&mut counter, || {
+    let barrier = Barrier::new(THREADS);
+    thread::scope(|s| {
+      for _ in 0..THREADS {
+        s.spawn(|| {
+          let counter = cell.borrow_shared();
+          // Every thread holds its borrow at the same time here.
+          barrier.wait();
+          counter.hits.fetch_add(1, Ordering::SeqCst);
+          barrier.wait();
+        });
+      }
+    });
+
+    let shared = cell.borrow_shared();
+    assert!(
+      cell.diagnostic_dump().contains("readers: 1\n"),
+      "dump should count readers"
+    );
+    assert!(
+      matches!(cell.try_borrow(), Err(BorrowError::AlreadyBorrowed)),
+      "exclusive borrow should be refused while shared"
+    );
+    assert!(
+      matches!(cell.try_reclaim(), Err(ReclaimError::InUse)),
+      "reclaim should be refused while shared"
+    );
+    drop(shared);
+
+    let exclusive = cell.borrow();
+    assert!(
+      matches!(cell.try_borrow_shared(), Err(BorrowError::AlreadyBorrowed)),
+      "shared borrow should be refused while exclusive"
+    );
+    drop(exclusive);
+  });
+  assert_eq!(counter.hits.into_inner(), THREADS);
+  assert!(
+    matches!(cell.try_borrow_shared(), Err(BorrowError::Unavailable)),
+    "reclaimed cell should have nothing to share"
+  );
+}
+
+#[test]
+fn borrow_or() {
+  let cell = FfiCell::<i32>::new();
+  assert_eq!(cell.borrow_or(-1, |value| *value), -1, "empty cell");
+
+  let mut value = 5;
+  cell.run(&mut value, || {
+    assert_eq!(cell.borrow_or(-1, |value| *value * 2), 10, "available");
+
+    let guard = cell.borrow();
+    assert_eq!(cell.borrow_or(-1, |value| *value), -1, "borrowed elsewhere");
+    drop(guard);
+  });
+}
+
+#[test]
+fn typestate() {
+  let cell = FfiCell::<i32>::new();
+  let mut value = 1;
+  let lent = unsafe { cell.lend_typed(&mut value) };
+
+  let mut borrowed = lent.borrow();
+  *borrowed += 1;
+  assert!(
+    matches!(cell.try_borrow(), Err(BorrowError::AlreadyBorrowed)),
+    "typestate borrow should hold the cell's borrow"
+  );
+  let lent = borrowed.release();
+  assert!(
+    !cell.in_use.load(Ordering::SeqCst),
+    "release should end the borrow"
+  );
+
+  let mut borrowed = lent.borrow();
+  *borrowed += 1;
+  borrowed.release().reclaim();
+  assert!(
+    matches!(cell.try_reclaim(), Err(ReclaimError::Unavailable)),
+    "reclaim should empty the cell"
+  );
+  assert_eq!(value, 3);
+
+  let mut value = 1;
+  let lent = unsafe { cell.lend_typed(&mut value) };
+  *lent.borrow() += 1;
+  assert!(
+    matches!(cell.try_reclaim(), Err(ReclaimError::Unavailable)),
+    "dropping a borrowed token should release and reclaim"
+  );
+  assert_eq!(value, 2);
+
+  let lent = unsafe { cell.lend_typed(&mut value) };
+  cell.reclaim();
+  lent.reclaim();
+}
+
+#[test]
+fn borrow_to_c_status() {
+  let cell = FfiCell::<i32>::new();
+  let mut value = 1;
+  cell.run(&mut value, || {
+    assert_eq!(cell.borrow_to_c_status(|v| *v += 1), STATUS_OK);
+
+    let guard = cell.borrow();
+    assert_eq!(
+      cell.borrow_to_c_status(|_| unreachable!()),
+      STATUS_BORROW_FAILED
+    );
+    drop(guard);
+
+    assert_eq!(cell.borrow_to_c_status(|_| panic!("boom")), STATUS_PANICKED);
+    assert!(
+      !cell.in_use.load(Ordering::SeqCst),
+      "a caught panic should still release the borrow"
+    );
+  });
+  assert_eq!(value, 2);
+}
+
+#[test]
+fn typestate_borrow_conflict_panics_once() {
+  let cell = FfiCell::<i32>::new();
+  let mut value = 1;
+  let lent = unsafe { cell.lend_typed(&mut value) };
+  let guard = cell.borrow();
+
+  let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+    lent.borrow();
+  }));
+  assert!(result.is_err(), "conflicting typestate borrow should panic");
+
+  drop(guard);
+  cell.reclaim();
+  assert_eq!(value, 1);
+}
+
+#[test]
+fn finalizers_stay_with_their_loans() {
+  use std::{
+    sync::{Arc, Barrier, atomic::AtomicBool},
+    thread,
+  };
+
+  // Marks the loan as finished once its finalizer has run or been dropped.
+  struct Done(Arc<AtomicBool>);
+
+  impl Drop for Done {
+    fn drop(&mut self) {
+      self.0.store(true, Ordering::SeqCst);
+    }
+  }
+
+  let cell = FfiCell::<usize>::new();
+  let lent = Arc::new(AtomicUsize::new(0));
+  let finalized = Arc::new(AtomicUsize::new(0));
+  let mismatched = Arc::new(AtomicUsize::new(0));
+  let start = Barrier::new(4);
+  thread::scope(|s| {
+    for id in 0..4 {
+      let cell = &cell;
+      let start = &start;
+      let lent = Arc::clone(&lent);
+      let finalized = Arc::clone(&finalized);
+      let mismatched = Arc::clone(&mismatched);
+      s.spawn(move || {
+        let mut value = id;
+        start.wait();
+        for _ in 0..20000 {
+          let done = Arc::new(AtomicBool::new(false));
+          let finalizer = {
+            let done = Done(Arc::clone(&done));
+            let finalized = Arc::clone(&finalized);
+            let mismatched = Arc::clone(&mismatched);
+            move |value: &mut usize| {
+              let _done = done;
+              finalized.fetch_add(1, Ordering::SeqCst);
+              if *value != id {
+                mismatched.fetch_add(1, Ordering::SeqCst);
+              }
+            }
+          };
+          if unsafe { cell.try_lend_with_finalizer(&mut value, finalizer) }
+            .is_err()
+          {
+            continue;
+          }
+          lent.fetch_add(1, Ordering::SeqCst);
+          while !done.load(Ordering::SeqCst) {
+            let _ = cell.try_reclaim();
+          }
+        }
+      });
+    }
+  });
+
+  assert_eq!(
+    finalized.load(Ordering::SeqCst),
+    lent.load(Ordering::SeqCst),
+    "every loan's finalizer should run"
+  );
+  assert_eq!(
+    mismatched.load(Ordering::SeqCst),
+    0,
+    "finalizers should only run on their own loan"
+  );
+}
+
+/// Drives pseudo-random sequences of the unsafe lend, borrow and reclaim
+/// api that respect its contracts, and checks every result against a model
+/// of the cell's state.
+#[test]
+fn unsafe_api_model() {
+  use std::sync::{Arc, Mutex};
+
+  const OBJECTS: usize = 4;
+  const SEQUENCES: u64 = 500;
+  const OPS: usize = 200;
+
+  for seed in 1..=SEQUENCES {
+    // xorshift64*, so that failures reproduce from the seed alone.
+    let mut state = seed;
+    let mut next = move || {
+      state ^= state >> 12;
+      state ^= state << 25;
+      state ^= state >> 27;
+      state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32
+    };
+
+    // The objects are boxed so their addresses stay put, and they are only
+    // touched directly once the cell no longer points at any of them.
+    let mut objects: Vec<Box<i64>> =
+      (0..OBJECTS as i64).map(Box::new).collect();
+    let mut expected: Vec<i64> = (0..OBJECTS as i64).collect();
+    let finalized = Arc::new(Mutex::new(Vec::new()));
+    let mut expected_finalized = Vec::new();
+
+    let cell = FfiCell::<i64>::new();
+    // The lent object's index, and whether it was lent with a finalizer.
+    let mut lent: Option<(usize, bool)> = None;
+    let mut guard: Option<FfiGuard<'_, i64>> = None;
+    let mut detached: Option<DetachedBorrow<'_, i64>> = None;
+    let mut raw: Option<*mut c_void> = None;
+    let mut shared: Vec<SharedGuard<'_, i64>> = Vec::new();
+
+    for _ in 0..OPS {
+      let exclusive = guard.is_some() || detached.is_some() || raw.is_some();
+      let borrowed = exclusive || !shared.is_empty();
+      let op = next();
+      let pick = next() as usize;
+      // Never create a reference to the object that is currently lent.
+      let index = match lent {
+        Some((lent, _)) => (lent + 1 + pick % (OBJECTS - 1)) % OBJECTS,
+        None => pick % OBJECTS,
+      };
+      match op % 14 {
+        0 => {
+          let result = unsafe { cell.try_lend(objects[index].as_mut()) };
+          match (result, lent) {
+            (Err(LendError::AlreadyLent), Some(_)) if exclusive => {},
+            (Err(LendError::AlreadyHasLoan), Some(_)) if !exclusive => {},
+            (Ok(()), None) => lent = Some((index, false)),
+            (result, _) => panic!("seed {seed}: lend returned {result:?}"),
+          }
+        },
+        1 => {
+          let finalized = Arc::clone(&finalized);
+          let result = unsafe {
+            cell
+              .try_lend_with_finalizer(objects[index].as_mut(), move |value| {
+                finalized.lock().unwrap().push(*value)
+              })
+          };
+          match (result, lent) {
+            (Err(LendError::AlreadyLent), Some(_)) if exclusive => {},
+            (Err(LendError::AlreadyHasLoan), Some(_)) if !exclusive => {},
+            (Ok(()), None) => lent = Some((index, true)),
+            (result, _) => {
+              panic!("seed {seed}: lend with finalizer returned {result:?}")
+            },
+          }
+        },
+        2 => match (cell.try_borrow(), lent) {
+          (Err(BorrowError::AlreadyBorrowed), Some(_)) if borrowed => {},
+          (Err(BorrowError::Unavailable), None) => {},
+          (Ok(borrow), Some((index, _))) if !borrowed => {
+            assert_eq!(*borrow, expected[index], "seed {seed}: stale borrow");
+            guard = Some(borrow);
+          },
+          (result, _) => {
+            panic!("seed {seed}: borrow returned {:?}", result.err())
+          },
+        },
+        3 => {
+          if let (Some(guard), Some((index, _))) = (&mut guard, lent) {
+            let value = op as i64;
+            **guard = value;
+            expected[index] = value;
+          }
+        },
+        4 => drop(guard.take()),
+        5 => {
+          if let Some(borrow) = guard.take() {
+            detached = Some(borrow.detach());
+          }
+        },
+        6 => {
+          if let Some(handle) = detached.take() {
+            guard = Some(handle.reattach());
+          }
+        },
+        7 => {
+          if let Some(handle) = detached.take() {
+            raw = Some(handle.into_raw());
+          }
+        },
+        8 => {
+          if let Some(ptr) = raw.take() {
+            detached = Some(unsafe { DetachedBorrow::from_raw(ptr) });
+          }
+        },
+        9 => match (cell.try_borrow_shared(), lent) {
+          (Err(BorrowError::AlreadyBorrowed), Some(_)) if exclusive => {},
+          (Err(BorrowError::Unavailable), None) => {},
+          (Ok(borrow), Some((index, _))) if !exclusive => {
+            assert_eq!(*borrow, expected[index], "seed {seed}: stale borrow");
+            shared.push(borrow);
+          },
+          (result, _) => {
+            panic!("seed {seed}: shared borrow returned {:?}", result.err())
+          },
+        },
+        10 => drop(shared.pop()),
+        11 => match (cell.try_reclaim(), lent) {
+          (Err(ReclaimError::InUse), Some(_)) if borrowed => {},
+          (Err(ReclaimError::Unavailable), None) => {},
+          (Ok(()), Some((index, finalizer))) if !borrowed => {
+            if finalizer {
+              expected_finalized.push(expected[index]);
+            }
+            lent = None;
+          },
+          (result, _) => panic!("seed {seed}: reclaim returned {result:?}"),
+        },
+        12 => match (cell.reset(), lent) {
+          (Err(ReclaimError::InUse), Some(_)) if borrowed => {},
+          (Ok(()), _) if !borrowed => {
+            if let Some((index, true)) = lent {
+              expected_finalized.push(expected[index]);
+            }
+            lent = None;
+          },
+          (result, _) => panic!("seed {seed}: reset returned {result:?}"),
+        },
+        _ => {
+          // Forcing a reclaim is only allowed with no borrow alive, and it
+          // drops the finalizer without running it.
+          if !borrowed {
+            unsafe { cell.force_reclaim() };
+            lent = None;
+          }
+        },
+      }
+    }
+
+    drop(guard);
+    drop(detached);
+    if let Some(ptr) = raw {
+      drop(unsafe { DetachedBorrow::<i64>::from_raw(ptr) });
+    }
+    drop(shared);
+    if let Some((index, true)) = lent {
+      expected_finalized.push(expected[index]);
+    }
+    cell
+      .reset()
+      .expect("cell should reset once all borrows are released");
+    let actual: Vec<i64> = objects.iter().map(|object| **object).collect();
+    assert_eq!(actual, expected, "seed {seed}: writes were lost");
+    assert_eq!(
+      *finalized.lock().unwrap(),
+      expected_finalized,
+      "seed {seed}: finalizers ran on the wrong values"
+    );
+  }
+}
+
+#[test]
+fn reclaim_does_not_look_borrowed() {
+  use std::{
+    sync::{Barrier, atomic::AtomicBool},
+    thread,
+  };
+
+  let cell = FfiCell::<i32>::new();
+  let done = AtomicBool::new(false);
+  let start = Barrier::new(2);
+  thread::scope(|s| {
+    s.spawn(|| {
+      start.wait();
+      while !done.load(Ordering::SeqCst) {
+        assert!(
+          matches!(cell.reset(), Ok(()) | Err(ReclaimError::InUse)),
+          "reset should only fail while the value is borrowed"
+        );
+      }
+    });
+
+    // Stops the resetting thread even if an assertion below fails.
+    let _done = ScopeGuard::new(|| done.store(true, Ordering::SeqCst));
+    let mut value = 0;
+    start.wait();
+    for _ in 0..10000 {
+      unsafe { cell.try_lend(&mut value) }
+        .expect("a concurrent reclaim should not make the cell look lent");
+      assert!(
+        matches!(cell.try_borrow(), Ok(_) | Err(BorrowError::Unavailable)),
+        "a concurrent reclaim should not make the cell look borrowed"
+      );
+      assert!(
+        matches!(cell.try_reclaim(), Ok(()) | Err(ReclaimError::Unavailable)),
+        "reclaim should empty the cell"
+      );
+    }
+  });
+}
@@ -0,0 +1,40 @@
+use std::ffi::c_void;
+
+use crate::FfiGuard;
+
+/// A borrowed value bound to a closure, callable from C through a plain
+/// `void (*)(void *)` callback.
+///
+/// A panic in the closure aborts the process rather than unwinding into C.
+pub struct Trampoline<'g, T: Sync, F: FnMut(&mut T)> {
+  guard: FfiGuard<'g, T>,
+  f: F,
+}
+
+impl<'g, T: Sync, F: FnMut(&mut T)> Trampoline<'g, T, F> {
+  pub(crate) fn new(guard: FfiGuard<'g, T>, f: F) -> Self {
+    Self { guard, f }
+  }
+
+  /// Returns the callback and user data pointer to hand to C. Each call of
+  /// the callback with that pointer runs the closure on the borrowed value.
+  ///
+  /// The pointers are only valid while the trampoline is neither moved nor
+  /// dropped, and the callback must not be invoked again from inside the
+  /// closure.
+  pub fn as_c_callback(
+    &mut self,
+  ) -> (unsafe extern "C" fn(*mut c_void), *mut c_void) {
+    (call::<T, F>, (self as *mut Self).cast())
+  }
+
+  /// Stops handing the value to C and gives back the guard.
+  pub fn into_guard(self) -> FfiGuard<'g, T> {
+    self.guard
+  }
+}
+
+unsafe extern "C" fn call<T: Sync, F: FnMut(&mut T)>(data: *mut c_void) {
+  let trampoline = unsafe { &mut *data.cast::<Trampoline<'_, T, F>>() };
+  (trampoline.f)(&mut trampoline.guard);
+}
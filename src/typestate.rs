@@ -0,0 +1,95 @@
+use std::{
+  marker::PhantomData,
+  mem,
+  ops::{Deref, DerefMut},
+};
+
+use crate::{FfiCell, FfiGuard, ReclaimError, ResultExt, receipt};
+
+/// A loan whose lifecycle is checked at compile time: it can be borrowed,
+/// and only reclaimed once the borrow has been released.
+///
+/// ```compile_fail
+/// # use ffi_cell::FfiCell;
+/// let cell = FfiCell::<i32>::new();
+/// let mut value = 0;
+/// let lent = unsafe { cell.lend_typed(&mut value) };
+/// let borrowed = lent.borrow();
+/// borrowed.reclaim();
+/// ```
+///
+/// ```compile_fail
+/// # use ffi_cell::FfiCell;
+/// let cell = FfiCell::<i32>::new();
+/// let mut value = 0;
+/// let lent = unsafe { cell.lend_typed(&mut value) };
+/// let borrowed = lent.borrow();
+/// lent.reclaim();
+/// ```
+///
+/// Dropping the token reclaims the value, and dropping a [`Borrowed`]
+/// releases and then reclaims it. Like a [`LoanReceipt`](crate::LoanReceipt)
+/// with [`ReceiptPolicy::Abort`](crate::ReceiptPolicy::Abort), both do
+/// nothing if the value was already reclaimed through the cell, and abort
+/// the process if it is still borrowed through the cell.
+pub struct Lent<'a, T: Sync> {
+  pub(crate) cell: &'a FfiCell<T>,
+  pub(crate) _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: Sync> Lent<'a, T> {
+  #[track_caller]
+  pub fn borrow(self) -> Borrowed<'a, T> {
+    match self.cell.try_borrow() {
+      Ok(guard) => Borrowed { guard, lent: self },
+      Err(err) => {
+        // Reclaiming here would fail as well, and panic again while this
+        // panic unwinds.
+        mem::forget(self);
+        Err(err).unwrap_or_display_err()
+      },
+    }
+  }
+
+  pub fn reclaim(self) {
+    drop(self)
+  }
+}
+
+impl<'a, T: Sync> Drop for Lent<'a, T> {
+  fn drop(&mut self) {
+    match self.cell.try_reclaim() {
+      Ok(()) | Err(ReclaimError::Unavailable) => {},
+      Err(err) => receipt::abort_reclaim(err),
+    }
+  }
+}
+
+pub struct Borrowed<'a, T: Sync> {
+  // Declared first so that the borrow is released before the loan is
+  // reclaimed.
+  guard: FfiGuard<'a, T>,
+  lent: Lent<'a, T>,
+}
+
+impl<'a, T: Sync> Borrowed<'a, T> {
+  pub fn release(self) -> Lent<'a, T> {
+    let Self { guard, lent } = self;
+    drop(guard);
+    lent
+  }
+}
+
+impl<'a, T: Sync> Deref for Borrowed<'a, T> {
+  type Target = T;
+
+  fn deref(&self) -> &Self::Target {
+    &self.guard
+  }
+}
+
+impl<'a, T: Sync> DerefMut for Borrowed<'a, T> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.guard
+  }
+}